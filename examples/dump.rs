@@ -0,0 +1,21 @@
+use fast_tracer::{dump_svg, initialize_logger};
+use tracing::{span, Level};
+
+fn main() {
+    initialize_logger();
+
+    let workers: Vec<_> = (0..2)
+        .map(|_| {
+            std::thread::spawn(|| {
+                let span = span!(Level::TRACE, "worker");
+                let _enter = span.enter();
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            })
+        })
+        .collect();
+    workers
+        .into_iter()
+        .for_each(|worker| worker.join().expect("worker panicked"));
+
+    dump_svg("dump.svg").expect("failed saving svg file")
+}
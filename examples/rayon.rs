@@ -1,3 +1,4 @@
+use fast_tracer::parallel::TracedParallelIterator;
 use fast_tracer::svg;
 use rayon::prelude::*;
 
@@ -6,6 +7,7 @@ fn main() {
         let v = (0..10_000_000)
             .into_par_iter()
             .filter(|&e| e % 2 == 0)
+            .traced("filter")
             .fold(Vec::new, |mut v, e| {
                 v.push(e);
                 v
@@ -0,0 +1,191 @@
+//! Optional tracking of memory allocations.
+//! Declaring the `TracingAllocator` as global allocator counts all allocations
+//! of each thread. Counters are logged when entering and exiting spans
+//! so that each span gets the allocations done while it was the current span.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::ops::{Add, AddAssign, Sub};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Allocations counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Allocations {
+    /// Number of allocations.
+    pub(super) count: u64,
+    /// Bytes allocated.
+    pub(super) allocated: u64,
+    /// Bytes freed.
+    pub(super) freed: u64,
+}
+
+impl Allocations {
+    /// Counter number `index` (in field order).
+    pub(super) fn counter_mut(&mut self, index: u32) -> &mut u64 {
+        match index {
+            0 => &mut self.count,
+            1 => &mut self.allocated,
+            _ => &mut self.freed,
+        }
+    }
+    pub(super) fn counters(&self) -> [u64; 3] {
+        [self.count, self.allocated, self.freed]
+    }
+}
+
+impl Add for Allocations {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Allocations {
+            count: self.count.wrapping_add(other.count),
+            allocated: self.allocated.wrapping_add(other.allocated),
+            freed: self.freed.wrapping_add(other.freed),
+        }
+    }
+}
+
+impl AddAssign for Allocations {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other
+    }
+}
+
+impl Sub for Allocations {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Allocations {
+            count: self.count.wrapping_sub(other.count),
+            allocated: self.allocated.wrapping_sub(other.allocated),
+            freed: self.freed.wrapping_sub(other.freed),
+        }
+    }
+}
+
+/// Did the `TracingAllocator` allocate anything.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // const initialized and without destructor: it never allocates
+    static THREAD_ALLOCATIONS: Cell<Allocations> = const {
+        Cell::new(Allocations {
+            count: 0,
+            allocated: 0,
+            freed: 0,
+        })
+    };
+}
+
+/// Update current thread's counters.
+fn count(allocations: Allocations) {
+    if !TRACKING.load(Ordering::Relaxed) {
+        TRACKING.store(true, Ordering::Relaxed)
+    }
+    THREAD_ALLOCATIONS
+        .try_with(|counters| counters.set(counters.get() + allocations))
+        .ok();
+}
+
+/// Are allocations tracked.
+pub(super) fn is_tracking() -> bool {
+    TRACKING.load(Ordering::Relaxed)
+}
+
+/// Allocations done so far by the current thread.
+pub(super) fn thread_allocations() -> Allocations {
+    THREAD_ALLOCATIONS
+        .try_with(|counters| counters.get())
+        .unwrap_or_default()
+}
+
+/// A global allocator wrapping `System` and counting allocations per span.
+///
+/// ```
+/// #[global_allocator]
+/// static ALLOCATOR: fast_tracer::TracingAllocator = fast_tracer::TracingAllocator;
+/// # fn main() {}
+/// ```
+///
+/// Allocations (count and bytes) then appear in `stats` and in svg tooltips.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TracingAllocator;
+
+unsafe impl GlobalAlloc for TracingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(Allocations {
+            count: 1,
+            allocated: layout.size() as u64,
+            freed: 0,
+        });
+        System.alloc(layout)
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(Allocations {
+            count: 1,
+            allocated: layout.size() as u64,
+            freed: 0,
+        });
+        System.alloc_zeroed(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count(Allocations {
+            count: 0,
+            allocated: 0,
+            freed: layout.size() as u64,
+        });
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(Allocations {
+            count: 1,
+            allocated: new_size as u64,
+            freed: layout.size() as u64,
+        });
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{extract_spans, RECORDING_TESTS};
+    use super::*;
+
+    #[test]
+    fn span_allocations_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        // tests do not use the `TracingAllocator` as global allocator:
+        // only the allocations below are counted
+        let layout = Layout::from_size_align(1000, 8).unwrap();
+        tracing::subscriber::with_default(super::super::FastSubscriber::new(), || {
+            tracing::span!(tracing::Level::TRACE, "allocating").in_scope(|| {
+                tracing::span!(tracing::Level::TRACE, "child").in_scope(|| unsafe {
+                    let small = TracingAllocator.alloc(Layout::from_size_align(10, 8).unwrap());
+                    TracingAllocator.dealloc(small, Layout::from_size_align(10, 8).unwrap());
+                });
+                unsafe {
+                    let buffer = TracingAllocator.alloc(layout);
+                    let buffer = TracingAllocator.realloc(buffer, layout, 2000);
+                    TracingAllocator.dealloc(buffer, Layout::from_size_align(2000, 8).unwrap());
+                }
+            })
+        });
+        assert!(is_tracking());
+        let spans = extract_spans();
+        let span = |name| spans.values().find(|s| s.name == name).unwrap();
+        assert_eq!(
+            span("allocating").allocations,
+            Allocations {
+                count: 2,
+                allocated: 3000,
+                freed: 3000,
+            }
+        );
+        assert_eq!(
+            span("child").allocations,
+            Allocations {
+                count: 1,
+                allocated: 10,
+                freed: 10,
+            }
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Assertions on a trace's parallel performance, to catch regressions in tests.
+use super::{Span, Trace};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Executions (start, end, thread) of a span.
+/// Spans which were not recorded by us have a single one.
+fn executions(span: &Span) -> Vec<(u128, u128, usize)> {
+    if span.intervals.is_empty() {
+        vec![(span.start, span.end, span.execution_thread)]
+    } else {
+        span.intervals.clone()
+    }
+}
+
+impl Trace {
+    /// Time between the start of the first span and the end of the last one.
+    fn wall_time(&self) -> u128 {
+        let start = self.spans.values().map(|s| s.start).min().unwrap_or(0);
+        let end = self.spans.values().map(|s| s.end).max().unwrap_or(0);
+        end - start
+    }
+    /// Time each thread spent inside at least one span.
+    fn busy_time_per_thread(&self) -> HashMap<usize, u128> {
+        self.spans
+            .values()
+            .flat_map(executions)
+            .into_group_map_by(|(_, _, thread)| *thread)
+            .into_iter()
+            .map(|(thread, mut executions)| {
+                executions.sort_unstable();
+                // nested spans overlap: only count the union of executions
+                let (busy, _) =
+                    executions
+                        .iter()
+                        .fold((0, 0), |(busy, covered), &(start, end, _)| {
+                            let start = start.max(covered);
+                            if end > start {
+                                (busy + end - start, end)
+                            } else {
+                                (busy, covered)
+                            }
+                        });
+                (thread, busy)
+            })
+            .collect()
+    }
+    /// Average number of threads busy executing spans during the trace.
+    pub fn parallelism(&self) -> f64 {
+        let busy = self.busy_time_per_thread().values().sum::<u128>();
+        busy as f64 / self.wall_time().max(1) as f64
+    }
+    /// Fraction of time `threads` threads were not executing spans,
+    /// between the start of the first span and the end of the last one.
+    /// Threads which executed no span count as idle all the time: pass the size
+    /// of the thread pool (it is raised to the number of threads which executed spans).
+    pub fn idle_fraction(&self, threads: usize) -> f64 {
+        let busy_per_thread = self.busy_time_per_thread();
+        let threads = threads.max(busy_per_thread.len());
+        let available = threads as u128 * self.wall_time();
+        if available == 0 {
+            0.0
+        } else {
+            1.0 - busy_per_thread.values().sum::<u128>() as f64 / available as f64
+        }
+    }
+    /// Save the gantt diagram to the failure artifact (if any) and panic with given message.
+    fn fail(&self, message: String) -> ! {
+        if let Some(path) = &self.failure_svg {
+            match self.save_gantt_svg(path) {
+                Ok(()) => panic!("{}\ngantt diagram saved in {}", message, path.display()),
+                Err(e) => panic!(
+                    "{}\nfailed saving gantt diagram in {}: {}",
+                    message,
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        panic!("{}", message)
+    }
+    /// Save the gantt diagram in given file when an assertion fails.
+    ///
+    /// ```no_run
+    /// # fn sort(v: &mut [u32]) { v.sort() }
+    /// let mut v = (0..1_000_000).rev().collect::<Vec<u32>>();
+    /// let ((), trace) = fast_tracer::Trace::record(|| sort(&mut v));
+    /// trace
+    ///     .with_failure_svg("target/failed_trace.svg")
+    ///     .assert_parallelism_at_least(3.5);
+    /// ```
+    pub fn with_failure_svg<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.failure_svg = Some(path.as_ref().to_path_buf());
+        self
+    }
+    /// Panic if on average less than `parallelism` threads were busy.
+    pub fn assert_parallelism_at_least(&self, parallelism: f64) -> &Self {
+        let measured = self.parallelism();
+        if measured < parallelism {
+            let busy_per_thread = self.busy_time_per_thread();
+            self.fail(format!(
+                "parallelism is {:.2}, expected at least {:.2} \
+                 (wall time: {}ns, busy time per thread: {})",
+                measured,
+                parallelism,
+                self.wall_time(),
+                busy_per_thread
+                    .iter()
+                    .sorted()
+                    .map(|(thread, busy)| format!("{}: {}ns", thread, busy))
+                    .join(", ")
+            ))
+        }
+        self
+    }
+    /// Panic if `threads` threads were idle more than `fraction` of the time
+    /// (see `idle_fraction`).
+    pub fn assert_max_idle_fraction(&self, threads: usize, fraction: f64) -> &Self {
+        let measured = self.idle_fraction(threads);
+        if measured > fraction {
+            self.fail(format!(
+                "threads were idle {:.1}% of the time, expected at most {:.1}% \
+                 ({} threads, {} of them executing spans, wall time: {}ns)",
+                measured * 100.0,
+                fraction * 100.0,
+                threads,
+                self.busy_time_per_thread().len(),
+                self.wall_time()
+            ))
+        }
+        self
+    }
+    /// Panic if a span named `name` executed longer than `duration`
+    /// (time suspended between polls is not counted).
+    pub fn assert_no_span_longer_than(&self, name: &str, duration: Duration) -> &Self {
+        let too_long = self
+            .spans
+            .values()
+            .filter(|s| s.name == name && s.busy_time() > duration.as_nanos())
+            .sorted_by_key(|s| std::cmp::Reverse(s.busy_time()))
+            .collect::<Vec<_>>();
+        if !too_long.is_empty() {
+            self.fail(format!(
+                "{} '{}' spans executed longer than {:?}, the longest ones: {}",
+                too_long.len(),
+                name,
+                duration,
+                too_long
+                    .iter()
+                    .take(5)
+                    .map(|s| format!(
+                        "{:?} (thread {})",
+                        Duration::from_nanos(s.busy_time() as u64),
+                        s.execution_thread
+                    ))
+                    .join(", ")
+            ))
+        }
+        self
+    }
+    /// Panic if there are not exactly `count` spans named `name`.
+    pub fn assert_span_count(&self, name: &str, count: usize) -> &Self {
+        let measured = self.spans.values().filter(|s| s.name == name).count();
+        if measured != count {
+            self.fail(format!(
+                "found {} '{}' spans, expected {}",
+                measured, name, count
+            ))
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn trace(executions: &[(u128, u128, usize)]) -> Trace {
+        let spans = executions
+            .iter()
+            .enumerate()
+            .map(|(id, &(start, end, thread))| {
+                let mut span = Span::new(id as u64);
+                span.name = "task";
+                span.start = start;
+                span.end = end;
+                span.execution_thread = thread;
+                (id as u64, span)
+            })
+            .collect();
+        Trace {
+            spans,
+            origin: 0,
+            failure_svg: None,
+        }
+    }
+    #[test]
+    fn parallelism_test() {
+        // two threads, the second one idle half the time, nested spans on the first one
+        let trace = trace(&[(0, 10, 0), (2, 6, 0), (0, 5, 1)]);
+        assert!((trace.parallelism() - 1.5).abs() < 1e-9);
+        assert!((trace.idle_fraction(2) - 0.25).abs() < 1e-9);
+        // a third thread executed nothing
+        assert!((trace.idle_fraction(3) - 0.5).abs() < 1e-9);
+        assert!((trace.idle_fraction(1) - 0.25).abs() < 1e-9);
+        trace
+            .assert_parallelism_at_least(1.5)
+            .assert_max_idle_fraction(2, 0.25)
+            .assert_span_count("task", 3)
+            .assert_no_span_longer_than("task", Duration::from_nanos(10));
+        let failure =
+            std::panic::catch_unwind(|| trace.assert_parallelism_at_least(2.0)).unwrap_err();
+        assert_eq!(
+            failure.downcast_ref::<String>().unwrap(),
+            "parallelism is 1.50, expected at least 2.00 \
+             (wall time: 10ns, busy time per thread: 0: 10ns, 1: 5ns)"
+        );
+    }
+    #[test]
+    fn busy_time_test() {
+        // a future suspended most of the time
+        let mut trace = trace(&[(0, 100, 0)]);
+        let span = trace.spans.get_mut(&0).unwrap();
+        span.intervals = vec![(0, 5, 0), (95, 100, 0)];
+        trace.assert_no_span_longer_than("task", Duration::from_nanos(10));
+        let failure = std::panic::catch_unwind(|| {
+            trace.assert_no_span_longer_than("task", Duration::from_nanos(9))
+        })
+        .unwrap_err();
+        assert!(failure
+            .downcast_ref::<String>()
+            .unwrap()
+            .ends_with("the longest ones: 10ns (thread 0)"));
+    }
+    #[test]
+    fn failure_svg_test() {
+        let path = std::env::temp_dir().join("fast_tracer_failure_svg_test.svg");
+        std::fs::remove_file(&path).ok();
+        let trace = trace(&[(0, 10, 0)]).with_failure_svg(&path);
+        let failure = std::panic::catch_unwind(|| trace.assert_span_count("task", 2)).unwrap_err();
+        assert_eq!(
+            failure.downcast_ref::<String>().unwrap(),
+            &format!(
+                "found 1 'task' spans, expected 2\ngantt diagram saved in {}",
+                path.display()
+            )
+        );
+        assert!(std::fs::read_to_string(&path).unwrap().contains("<svg"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
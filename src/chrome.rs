@@ -0,0 +1,194 @@
+//! Import chrome trace event json files (as produced by many other tools)
+//! so that our renderers and analyses can run on them.
+//! We only handle duration events: begin (`B`), end (`E`) and complete (`X`).
+use super::spans::{kind_by_name, SpanKind, KIND_FIELD};
+use super::{Span, Trace};
+use itertools::Itertools;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A duration event, once begin and end are matched.
+struct Interval<'a> {
+    start: u128,
+    end: Option<u128>,
+    name: &'a str,
+    args: Option<&'a serde_json::Map<String, Value>>,
+}
+
+/// Chrome timestamps are in micro seconds.
+fn nanoseconds(micros: f64) -> u128 {
+    (micros.max(0.0) * 1_000.0).round() as u128
+}
+
+/// Spans hold `'static` strings so we leak (once) all imported strings.
+#[derive(Default)]
+struct Strings(HashMap<String, &'static str>);
+
+impl Strings {
+    fn get(&mut self, s: &str) -> &'static str {
+        if let Some(leaked) = self.0.get(s) {
+            return leaked;
+        }
+        let leaked: &'static str = Box::leak(s.to_owned().into_boxed_str());
+        self.0.insert(s.to_owned(), leaked);
+        leaked
+    }
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+pub(super) fn read_chrome_json<R: std::io::Read>(reader: R) -> std::io::Result<Trace> {
+    let json: Value = serde_json::from_reader(reader)?;
+    let events = json
+        .as_array()
+        .or_else(|| json.get("traceEvents").and_then(|e| e.as_array()))
+        .ok_or_else(|| invalid_data("no trace events found"))?;
+    let mut threads: HashMap<(String, String), Vec<Interval>> = HashMap::new();
+    let mut opened: HashMap<(String, String), Vec<usize>> = HashMap::new();
+    let mut max_time = 0;
+    for event in events {
+        let phase = event.get("ph").and_then(|p| p.as_str()).unwrap_or("");
+        let time = match event.get("ts").and_then(|t| t.as_f64()) {
+            Some(time) => nanoseconds(time),
+            None => continue,
+        };
+        let thread = (
+            event.get("pid").map(|p| p.to_string()).unwrap_or_default(),
+            event.get("tid").map(|t| t.to_string()).unwrap_or_default(),
+        );
+        let name = event.get("name").and_then(|n| n.as_str()).unwrap_or("");
+        let args = event.get("args").and_then(|a| a.as_object());
+        max_time = max_time.max(time);
+        match phase {
+            "B" | "X" => {
+                let end = if phase == "X" {
+                    let duration = event.get("dur").and_then(|d| d.as_f64()).unwrap_or(0.0);
+                    max_time = max_time.max(time + nanoseconds(duration));
+                    Some(time + nanoseconds(duration))
+                } else {
+                    None
+                };
+                let intervals = threads.entry(thread.clone()).or_default();
+                if phase == "B" {
+                    opened.entry(thread).or_default().push(intervals.len());
+                }
+                intervals.push(Interval {
+                    start: time,
+                    end,
+                    name,
+                    args,
+                });
+            }
+            "E" => {
+                let index = opened
+                    .get_mut(&thread)
+                    .and_then(|o| o.pop())
+                    .ok_or_else(|| invalid_data("end event without begin"))?;
+                let interval = &mut threads.get_mut(&thread).unwrap()[index];
+                interval.end = Some(time);
+                if let Some(end_args) = args {
+                    // chrome merges end arguments into begin arguments
+                    interval.args = interval.args.or(Some(end_args));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut strings = Strings::default();
+    let mut spans = HashMap::new();
+    let mut next_id = 1;
+    for (thread_index, (_, intervals)) in threads
+        .into_iter()
+        .sorted_by(|(t1, _), (t2, _)| t1.cmp(t2))
+        .enumerate()
+    {
+        // parents are the enclosing intervals on the same thread
+        let mut enclosing: Vec<(u64, u128)> = Vec::new();
+        for interval in intervals
+            .into_iter()
+            .sorted_by_key(|i| (i.start, std::cmp::Reverse(i.end.unwrap_or(max_time))))
+        {
+            let end = interval.end.unwrap_or(max_time);
+            while enclosing
+                .last()
+                .map(|(_, e)| *e <= interval.start)
+                .unwrap_or(false)
+            {
+                enclosing.pop();
+            }
+            let mut span = Span::new(next_id);
+            span.name = strings.get(interval.name);
+            span.kind = kind_by_name(span.name);
+            span.parent = enclosing.last().map(|(id, _)| *id);
+            span.start = interval.start;
+            span.end = end;
+            span.execution_thread = thread_index;
+            span.creation_thread = thread_index;
+            for (key, value) in interval.args.into_iter().flatten() {
+                let value = match value {
+                    Value::String(s) => strings.get(s),
+                    v => strings.get(&v.to_string()),
+                };
+                if key == KIND_FIELD {
+                    if let Some(kind) = SpanKind::from_field(value) {
+                        span.kind = kind;
+                        continue;
+                    }
+                }
+                span.fields.push((strings.get(key), value));
+            }
+            enclosing.push((next_id, end));
+            spans.insert(next_id, span);
+            next_id += 1;
+        }
+    }
+    // translate times like for recorded traces
+    let min_time = spans.values().map(|s| s.start).min().unwrap_or(0);
+    spans.values_mut().for_each(|s| {
+        s.start -= min_time;
+        s.end -= min_time;
+    });
+    Ok(Trace {
+        spans,
+        origin: 0,
+        failure_svg: None,
+    })
+}
+
+impl Trace {
+    /// Import a chrome trace event json file (either an array of events
+    /// or an object with a `traceEvents` array).
+    /// Each thread (pid and tid pair) is a thread of the trace and spans are
+    /// nested by time inclusion on their thread.
+    /// Imported strings are leaked.
+    pub fn from_chrome_json<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Trace> {
+        read_chrome_json(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn import_test() {
+        let json = r#"{"traceEvents": [
+            {"ph": "B", "name": "main", "ts": 10, "pid": 1, "tid": 1},
+            {"ph": "X", "name": "leaf", "ts": 11, "dur": 2, "pid": 1, "tid": 1, "args": {"size": 3}},
+            {"ph": "X", "name": "other", "ts": 12, "dur": 1, "pid": 1, "tid": 2},
+            {"ph": "E", "ts": 20, "pid": 1, "tid": 1}
+        ]}"#;
+        let trace = read_chrome_json(json.as_bytes()).unwrap();
+        assert_eq!(trace.spans.len(), 3);
+        let span = |name| trace.spans.values().find(|s| s.name == name).unwrap();
+        let (main, leaf, other) = (span("main"), span("leaf"), span("other"));
+        assert_eq!((main.start, main.end), (0, 10_000));
+        assert_eq!((leaf.start, leaf.end), (1_000, 3_000));
+        assert_eq!(leaf.parent, Some(main.id));
+        assert_eq!(leaf.fields, vec![("size", "3")]);
+        assert_eq!(other.parent, None);
+        assert_eq!(other.execution_thread, 1);
+    }
+}
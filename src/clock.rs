@@ -0,0 +1,196 @@
+//! Time sources used to timestamp events.
+//! Events only store raw `u64` ticks, which are converted to nano seconds
+//! during extraction.
+//! On x86_64 processors with an invariant time stamp counter we read it
+//! directly with `rdtsc` which is much cheaper than going through `Instant`.
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+
+/// A source of timestamps.
+pub trait Clock: Send + Sync {
+    /// Current time, in ticks since the clock's creation.
+    fn ticks(&self) -> u64;
+    /// Duration of a tick in nano seconds.
+    /// This is only called during extraction so it can be slow.
+    fn nanos_per_tick(&self) -> f64;
+}
+
+/// A clock based on `std::time::Instant`: ticks are nano seconds.
+#[derive(Debug)]
+pub struct InstantClock {
+    start: Instant,
+}
+
+impl InstantClock {
+    pub fn new() -> Self {
+        InstantClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for InstantClock {
+    fn default() -> Self {
+        InstantClock::new()
+    }
+}
+
+impl Clock for InstantClock {
+    fn ticks(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+    fn nanos_per_tick(&self) -> f64 {
+        1.0
+    }
+}
+
+/// A clock reading the processor's time stamp counter.
+/// It is calibrated against `Instant`: we remember both times at creation
+/// and compare them with current ones when converting ticks.
+/// The longer the recording, the more precise the calibration.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug)]
+pub struct TscClock {
+    start_ticks: u64,
+    start: Instant,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl TscClock {
+    /// Create a new tsc clock if the processor has an invariant tsc
+    /// (running at constant rate across frequency changes and sleep states).
+    // `__cpuid` is only safe on recent compilers
+    #[allow(unused_unsafe)]
+    pub fn new() -> Option<Self> {
+        // invariant tsc is advertised in bit 8 of edx for cpuid leaf 0x80000007
+        let max_leaf = unsafe { std::arch::x86_64::__cpuid(0x8000_0000) }.eax;
+        if max_leaf < 0x8000_0007 {
+            return None;
+        }
+        let power_management = unsafe { std::arch::x86_64::__cpuid(0x8000_0007) };
+        if power_management.edx & (1 << 8) == 0 {
+            return None;
+        }
+        Some(TscClock {
+            start_ticks: rdtsc(),
+            start: Instant::now(),
+        })
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn rdtsc() -> u64 {
+    unsafe { std::arch::x86_64::_rdtsc() }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Clock for TscClock {
+    fn ticks(&self) -> u64 {
+        rdtsc().wrapping_sub(self.start_ticks)
+    }
+    fn nanos_per_tick(&self) -> f64 {
+        let ticks = self.ticks();
+        let nanos = self.start.elapsed().as_nanos();
+        if ticks == 0 {
+            1.0
+        } else {
+            nanos as f64 / ticks as f64
+        }
+    }
+}
+
+/// The clock to use instead of the default one, if any.
+static CUSTOM_CLOCK: Mutex<Option<Box<dyn Clock>>> = Mutex::new(None);
+/// Did we start using the clock.
+static CLOCK_IN_USE: AtomicBool = AtomicBool::new(false);
+
+fn default_clock() -> Box<dyn Clock> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if let Some(clock) = TscClock::new() {
+            return Box::new(clock);
+        }
+    }
+    Box::new(InstantClock::new())
+}
+
+lazy_static! {
+    // we also keep the wall-clock time of the start
+    // in order to reconstruct absolute times
+    static ref CLOCK: (Box<dyn Clock>, SystemTime) = {
+        let mut custom_clock = CUSTOM_CLOCK.lock().unwrap();
+        CLOCK_IN_USE.store(true, Ordering::SeqCst);
+        let clock = custom_clock.take().unwrap_or_else(default_clock);
+        (clock, SystemTime::now())
+    };
+}
+
+/// Use given clock to timestamp all events.
+/// This must be called before creating the `FastSubscriber`.
+/// Returns false (and does nothing) if the clock is already in use.
+pub fn set_clock<C: Clock + 'static>(clock: C) -> bool {
+    let mut custom_clock = CUSTOM_CLOCK.lock().unwrap();
+    if CLOCK_IN_USE.load(Ordering::SeqCst) {
+        return false;
+    }
+    *custom_clock = Some(Box::new(clock));
+    true
+}
+
+/// Start the clock (if not already started).
+pub(super) fn initialize() {
+    lazy_static::initialize(&CLOCK);
+}
+
+/// Current time in ticks.
+pub(super) fn now() -> u64 {
+    CLOCK.0.ticks()
+}
+
+/// Duration of a tick in nano seconds.
+pub(super) fn nanos_per_tick() -> f64 {
+    CLOCK.0.nanos_per_tick()
+}
+
+/// Unix time (in nano seconds) of the origin of all recorded times.
+pub(super) fn start_unix_nanos() -> u128 {
+    CLOCK
+        .1
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Check the clock is monotonic and measures a sleep plausibly.
+    fn check_clock(clock: &dyn Clock) {
+        let mut previous = clock.ticks();
+        for _ in 0..10_000 {
+            let ticks = clock.ticks();
+            assert!(ticks >= previous);
+            previous = ticks;
+        }
+        let start = clock.ticks();
+        std::thread::sleep(Duration::from_millis(20));
+        let elapsed = (clock.ticks() - start) as f64 * clock.nanos_per_tick();
+        assert!(elapsed >= 20_000_000.0 * 0.9);
+        assert!(elapsed < 1_000_000_000.0);
+    }
+    #[test]
+    fn instant_clock_test() {
+        check_clock(&InstantClock::new())
+    }
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn tsc_clock_test() {
+        if let Some(clock) = TscClock::new() {
+            check_clock(&clock)
+        }
+    }
+}
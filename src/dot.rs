@@ -0,0 +1,79 @@
+//! Export the tasks graph in graphviz's dot format.
+//! Each task is a node with its label, start, duration and thread as attributes.
+use super::graph::Dag;
+use super::svg::{time_string, COLORS};
+use super::{Graph, Trace};
+use std::io::Write;
+
+/// Escape given text to include it in a double quoted dot string.
+/// Non ascii characters are fine as they are.
+fn dot_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(super) fn write_dot<W: Write>(writer: &mut W, graph: &Graph) -> std::io::Result<()> {
+    let dag = Dag::new(&graph.root);
+    writeln!(writer, "digraph tasks {{")?;
+    writeln!(writer, "  node [shape=box, style=filled];")?;
+    for (index, task) in dag.tasks.iter().enumerate() {
+        writeln!(
+            writer,
+            "  t{} [label=\"{}\\n{}\", name=\"{}\", start={}, duration={}, thread={}, fillcolor={}];",
+            index,
+            dot_escape(task.label),
+            time_string(task.end - task.start),
+            dot_escape(task.label),
+            task.start,
+            task.end - task.start,
+            task.thread,
+            COLORS[task.thread % COLORS.len()]
+        )?;
+    }
+    for (index, successors) in dag.successors.iter().enumerate() {
+        for successor in successors {
+            writeln!(writer, "  t{} -> t{};", index, successor)?;
+        }
+    }
+    writeln!(writer, "}}")
+}
+
+/// Records the execution of `op` and saves its tasks graph as a graphviz dot file.
+pub fn dot<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(path: P, op: F) -> std::io::Result<R> {
+    let (r, trace) = Trace::record(op);
+    trace.save_dot(path)?;
+    Ok(r)
+}
+
+/// Shortcut for `Trace::extract().save_dot(path)`.
+pub fn dump_dot<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_dot(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::spans::Span;
+    use super::*;
+    use std::collections::HashMap;
+    #[test]
+    fn dot_test() {
+        let mut span = Span::new(1);
+        span.name = "say \"héllo\"";
+        span.end = 10;
+        let spans: HashMap<u64, Span> = std::iter::once((1, span)).collect();
+        let mut dot = Vec::new();
+        write_dot(&mut dot, &Graph::new(&spans)).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph tasks {"));
+        // quotes are escaped, non ascii characters are kept
+        assert!(dot.contains(r#"t0 [label="say \"héllo\"\n10ns", name="say \"héllo\"", start=0"#));
+    }
+}
@@ -1,85 +1,452 @@
 //! Events and the places they are stored into.
+use super::allocations::Allocations;
+use super::clock::nanos_per_tick;
+use super::intern::{intern, intern_static, strings, Strings, STRING_ID_BITS};
+use super::list::AtomicLinkedList;
+#[cfg(feature = "log")]
+use super::log_bridge::LogRecord;
+use super::overhead::{compensation_per_event, CALIBRATION_IDS};
+use super::perf::{PerfCounters, PERF_COUNTERS};
+use super::scheduling::{Scheduling, SCHEDULING_COUNTERS};
+use super::spans::{kind_by_name, SpanKind, KIND_FIELD, THREAD_NAME_FIELD};
 use super::{Span, Storage};
-use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::collections::LinkedList;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 
-pub(super) enum RawEvent {
+/// A logged event, packed in 16 bytes.
+///
+/// The header holds the event kind (3 bits), a string id (21 bits) and the span id (40 bits).
+/// The payload is the parent span id for `NewSpan` (whose name is the string),
+/// the value's string id for `StrField` (whose field name is the string),
+/// clock ticks for `Enter` and `Exit`
+/// and a counter's value for `Allocations`, `Scheduling` and `Perf`
+/// (the string bits select the counter and tell if we are exiting the span).
+/// `Log` events have no span id: their payload is the index of their record
+/// in the thread's log records and they are attached to the active span during extraction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct RawEvent {
+    header: u64,
+    payload: u64,
+}
+
+const SPAN_ID_BITS: u32 = 40;
+const KIND_SHIFT: u32 = SPAN_ID_BITS + STRING_ID_BITS;
+const NEW_SPAN: u64 = 0;
+const STR_FIELD: u64 = 1;
+const ENTER: u64 = 2;
+const EXIT: u64 = 3;
+const ALLOCATIONS: u64 = 4;
+const SCHEDULING: u64 = 5;
+#[cfg(feature = "perf")]
+const PERF: u64 = 6;
+#[cfg(feature = "log")]
+const LOG: u64 = 7;
+
+/// A `RawEvent` decoded back during extraction.
+pub(super) enum DecodedEvent {
+    /// Span id, name and parent id (0 if none).
     NewSpan(u64, &'static str, u64),
+    /// Span id, field name and value.
     StrField(u64, &'static str, &'static str),
-    Enter(u64, u128),
-    Exit(u64, u128),
+    /// Span id and clock ticks.
+    Enter(u64, u64),
+    /// Span id and clock ticks.
+    Exit(u64, u64),
+    /// Span id, are we exiting the span, counter index and value.
+    Allocations(u64, bool, u32, u64),
+    /// Span id, are we exiting the span, counter index and value.
+    Scheduling(u64, bool, u32, u64),
+    /// Span id, are we exiting the span, counter index and value.
+    Perf(u64, bool, u32, u64),
+    /// Index of a log record in the thread's records.
+    #[cfg(feature = "log")]
+    Log(usize),
+}
+
+impl RawEvent {
+    fn pack(kind: u64, string: u32, id: u64, payload: u64) -> Self {
+        debug_assert!(id < 1 << SPAN_ID_BITS);
+        RawEvent {
+            header: kind << KIND_SHIFT | (string as u64) << SPAN_ID_BITS | id,
+            payload,
+        }
+    }
+    pub(super) fn new_span(id: u64, name: &'static str, parent: u64) -> Self {
+        RawEvent::pack(NEW_SPAN, intern_static(name), id, parent)
+    }
+    pub(super) fn str_field(id: u64, field_name: &'static str, value: &str) -> Self {
+        RawEvent::pack(
+            STR_FIELD,
+            intern_static(field_name),
+            id,
+            intern(value) as u64,
+        )
+    }
+    pub(super) fn enter(id: u64, ticks: u64) -> Self {
+        RawEvent::pack(ENTER, 0, id, ticks)
+    }
+    pub(super) fn exit(id: u64, ticks: u64) -> Self {
+        RawEvent::pack(EXIT, 0, id, ticks)
+    }
+    fn counter(kind: u64, id: u64, exiting: bool, counter: u32, value: u64) -> Self {
+        let selector = counter << 1 | exiting as u32;
+        RawEvent::pack(kind, selector, id, value)
+    }
+    pub(super) fn allocations(id: u64, exiting: bool, counter: u32, value: u64) -> Self {
+        RawEvent::counter(ALLOCATIONS, id, exiting, counter, value)
+    }
+    pub(super) fn scheduling(id: u64, exiting: bool, counter: u32, value: u64) -> Self {
+        RawEvent::counter(SCHEDULING, id, exiting, counter, value)
+    }
+    #[cfg(feature = "perf")]
+    pub(super) fn perf(id: u64, exiting: bool, counter: u32, value: u64) -> Self {
+        RawEvent::counter(PERF, id, exiting, counter, value)
+    }
+    #[cfg(feature = "log")]
+    fn log(index: usize) -> Self {
+        RawEvent::pack(LOG, 0, 0, index as u64)
+    }
+    /// Id of the span the event is about (0 for `Log` events).
+    fn span_id(&self) -> u64 {
+        self.header & ((1 << SPAN_ID_BITS) - 1)
+    }
+    /// Decode the event.
+    pub(super) fn decode(&self, strings: &Strings) -> DecodedEvent {
+        let id = self.span_id();
+        let string = ((self.header >> SPAN_ID_BITS) & ((1 << STRING_ID_BITS) - 1)) as u32;
+        match self.header >> KIND_SHIFT {
+            NEW_SPAN => DecodedEvent::NewSpan(id, strings.get(string), self.payload),
+            STR_FIELD => {
+                DecodedEvent::StrField(id, strings.get(string), strings.get(self.payload as u32))
+            }
+            ENTER => DecodedEvent::Enter(id, self.payload),
+            EXIT => DecodedEvent::Exit(id, self.payload),
+            #[cfg(feature = "log")]
+            LOG => DecodedEvent::Log(self.payload as usize),
+            kind => {
+                let (exiting, counter) = (string & 1 == 1, string >> 1);
+                match kind {
+                    ALLOCATIONS => DecodedEvent::Allocations(id, exiting, counter, self.payload),
+                    SCHEDULING => DecodedEvent::Scheduling(id, exiting, counter, self.payload),
+                    _ => DecodedEvent::Perf(id, exiting, counter, self.payload),
+                }
+            }
+        }
+    }
 }
 
-lazy_static! {
-    static ref LOGS: Mutex<LinkedList<Arc<Storage<RawEvent>>>> = Mutex::new(LinkedList::new());
+/// Events logged by a thread.
+struct ThreadLog {
+    events: Storage<RawEvent>,
+    /// Did the thread exit.
+    finished: AtomicBool,
+    /// Interned name of the thread plus one (0 if unnamed).
+    name: AtomicU32,
+    /// Records of the `log` crate, referenced by index from `Log` events.
+    #[cfg(feature = "log")]
+    records: Mutex<Vec<LogRecord>>,
 }
 
+impl ThreadLog {
+    /// Discard all events and log records.
+    fn reset(&self) {
+        self.events.reset();
+        #[cfg(feature = "log")]
+        self.records.lock().unwrap().clear();
+    }
+}
+
+/// Each thread's handle on its log, marking it as finished when the thread exits.
+struct ThreadLogHandle(Arc<ThreadLog>);
+
+impl Drop for ThreadLogHandle {
+    fn drop(&mut self) {
+        self.0.finished.store(true, Ordering::SeqCst)
+    }
+}
+
+/// All threads logs, most recent first.
+/// Threads register without locking, logs of finished threads are only removed
+/// (after extracting their events) by `extract_spans_with_offset`.
+static LOGS: AtomicLinkedList<Arc<ThreadLog>> = AtomicLinkedList::new();
+/// Serializes iterations on `LOGS` and removals from it.
+static LOGS_ACCESS: Mutex<()> = Mutex::new(());
+
 thread_local! {
-    static THREAD_LOGS: Arc<Storage<RawEvent>> = {
-        let storage = Arc::new(Storage::new());
-        LOGS.lock().unwrap().push_back(storage.clone());
-        storage
-            };
+    static THREAD_LOGS: ThreadLogHandle = {
+        let log = Arc::new(ThreadLog {
+            events: Storage::new(),
+            finished: AtomicBool::new(false),
+            name: AtomicU32::new(0),
+            #[cfg(feature = "log")]
+            records: Mutex::new(Vec::new()),
+        });
+        LOGS.push_front_concurrently(log.clone());
+        ThreadLogHandle(log)
+    };
 }
 
 pub(super) fn reset_events() {
-    for log in LOGS.lock().unwrap().iter() {
+    let _access = LOGS_ACCESS.lock().unwrap();
+    for log in LOGS.iter() {
         log.reset()
     }
 }
 
+/// Name the current thread: all spans it creates get a `thread.name` field.
+pub(super) fn name_thread(name: &str) {
+    let name = intern(name) + 1;
+    THREAD_LOGS.with(|log| log.0.name.store(name, Ordering::SeqCst))
+}
+
+/// Discard all events logged by the current thread.
+pub(super) fn reset_thread_events() {
+    THREAD_LOGS.with(|log| log.0.reset())
+}
+
 pub(super) fn log_event(event: RawEvent) {
-    THREAD_LOGS.with(|logs| logs.push(event))
+    // events logged by other thread local destructors after ours are lost
+    THREAD_LOGS.try_with(|log| log.0.events.push(event)).ok();
 }
 
+/// Log given record of the `log` crate.
+#[cfg(feature = "log")]
+pub(super) fn log_record(record: LogRecord) {
+    THREAD_LOGS
+        .try_with(|log| {
+            let mut records = log.0.records.lock().unwrap();
+            records.push(record);
+            log.0.events.push(RawEvent::log(records.len() - 1))
+        })
+        .ok();
+}
+
+/// Tests recording and extracting spans must not run concurrently.
+#[cfg(test)]
+pub(super) static RECORDING_TESTS: Mutex<()> = Mutex::new(());
+
+#[cfg(test)]
 pub(super) fn extract_spans() -> HashMap<u64, Span> {
+    extract_spans_with_offset().1
+}
+
+/// Extract all spans, also returning the time (since the subscriber's start)
+/// subtracted from all of them.
+pub(super) fn extract_spans_with_offset() -> (u128, HashMap<u64, Span>) {
     let mut spans: HashMap<u64, Span> = HashMap::new();
     let mut min_time = std::u128::MAX;
     let mut max_time = std::u128::MIN;
     let mut entered_count = 0;
     let mut exited_count = 0;
     let mut all_active_spans = Vec::new();
+    let nanos_per_tick = nanos_per_tick();
+    let compensation = compensation_per_event();
+    let strings = strings();
+
+    let _access = LOGS_ACCESS.lock().unwrap();
+    // threads are numbered by registration order.
+    // we check if they are finished before reading their events
+    // so that we do not miss any event logged just before they exit.
+    let logs = LOGS
+        .iter()
+        .map(|log| (log, log.finished.load(Ordering::SeqCst)))
+        .collect::<Vec<_>>();
+    let finished_logs = logs
+        .iter()
+        .filter(|(_, finished)| *finished)
+        .map(|(log, _)| Arc::as_ptr(log))
+        .collect::<Vec<_>>();
 
-    for (thread, log) in LOGS.lock().unwrap().iter().enumerate() {
+    // threads which logged nothing (like the calibration thread) are not numbered
+    for (thread, (log, thread_name, finished)) in logs
+        .iter()
+        .rev()
+        .map(|(log, finished)| {
+            let name = log.name.load(Ordering::SeqCst);
+            (
+                log,
+                name.checked_sub(1).map(|name| strings.get(name)),
+                *finished,
+            )
+        })
+        .filter(|(log, _, _)| !log.events.is_empty())
+        .enumerate()
+    {
         let mut thread_active_spans = Vec::new();
-        for event in log.iter() {
-            match event {
-                RawEvent::NewSpan(id, name, parent) => {
-                    let span = spans.entry(*id).or_insert_with(|| Span::new(*id));
+        // allocations counters when entering active spans and allocations of their children
+        let mut allocations_stack: Vec<(Allocations, Allocations)> = Vec::new();
+        let mut exit_allocations = Allocations::default();
+        // scheduling counters when entering active spans (with whether they changed core)
+        // and when exiting the current one
+        let mut scheduling_stack: Vec<([u64; SCHEDULING_COUNTERS], bool)> = Vec::new();
+        let mut exit_scheduling = None;
+        // last sampled core
+        let mut last_cpu = None;
+        // perf counters when entering active spans and perf counters of their children
+        let mut perf_stack: Vec<([Option<u64>; PERF_COUNTERS], PerfCounters)> = Vec::new();
+        let mut exit_perf = [None; PERF_COUNTERS];
+        // remove tracing overhead from durations: each enter or exit inside an execution
+        // delays its end. Start times are kept as they are so that threads stay aligned.
+        let mut timed_events = 0;
+        // number of timed events when entering active spans and end of their last child
+        let mut compensation_stack: Vec<(u64, u128)> = Vec::new();
+        let to_nanos = |ticks: u64| (ticks as f64 * nanos_per_tick) as u128;
+        // calibration may run during the extraction: skip its events
+        #[cfg(feature = "log")]
+        let mut records = std::mem::take(&mut *log.records.lock().unwrap())
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        for event in log.events.iter().filter(|e| e.span_id() < CALIBRATION_IDS) {
+            match event.decode(&strings) {
+                DecodedEvent::NewSpan(id, name, parent) => {
+                    let span = spans.entry(id).or_insert_with(|| Span::new(id));
                     span.name = name;
-                    span.parent = if *parent == 0 {
+                    span.kind = kind_by_name(name);
+                    span.parent = if parent == 0 {
                         thread_active_spans.last().cloned()
                     } else {
-                        Some(*parent)
+                        Some(parent)
                     };
                     span.creation_thread = thread;
+                    if let Some(thread_name) = thread_name {
+                        span.fields.push((THREAD_NAME_FIELD, thread_name))
+                    }
                 }
-                RawEvent::Enter(id, time) => {
+                DecodedEvent::Enter(id, ticks) => {
+                    let time = to_nanos(ticks);
+                    compensation_stack.push((timed_events, time));
+                    timed_events += 1;
                     entered_count += 1;
-                    let span = spans.entry(*id).or_insert_with(|| Span::new(*id));
-                    span.start = *time;
-                    span.execution_thread = thread;
-                    thread_active_spans.push(*id);
-                    min_time = min_time.min(*time);
-                    max_time = max_time.max(*time);
+                    let span = spans.entry(id).or_insert_with(|| Span::new(id));
+                    // spans of futures are entered on each poll, maybe on different threads
+                    span.intervals.push((time, 0, thread));
+                    thread_active_spans.push(id);
+                    allocations_stack.push(Default::default());
+                    scheduling_stack.push(Default::default());
+                    perf_stack.push(Default::default());
+                    min_time = min_time.min(time);
+                    max_time = max_time.max(time);
                 }
-                RawEvent::Exit(id, time) => {
+                DecodedEvent::Exit(id, _) if thread_active_spans.last() != Some(&id) => {
+                    // entered before a previous extraction, which reset this log:
+                    // the span already ended then
+                    timed_events += 1;
+                    exit_allocations = Allocations::default();
+                    exit_scheduling = None;
+                    exit_perf = [None; PERF_COUNTERS];
+                }
+                DecodedEvent::Exit(id, ticks) => {
+                    let (enter_events, children_end) = compensation_stack.pop().unwrap();
+                    let overhead = ((timed_events - enter_events) as f64 * compensation) as u128;
+                    // children still end inside their parent
+                    let time = to_nanos(ticks).saturating_sub(overhead).max(children_end);
+                    if let Some((_, parent_children_end)) = compensation_stack.last_mut() {
+                        *parent_children_end = time;
+                    }
+                    timed_events += 1;
                     exited_count += 1;
-                    let span = spans.entry(*id).or_insert_with(|| Span::new(*id));
-                    span.end = *time;
-                    assert_eq!(span.execution_thread, thread);
-                    assert_eq!(thread_active_spans.pop(), Some(*id));
-                    min_time = min_time.min(*time);
-                    max_time = max_time.max(*time);
+                    let span = spans.entry(id).or_insert_with(|| Span::new(id));
+                    let interval = span
+                        .intervals
+                        .iter_mut()
+                        .rev()
+                        .find(|(_, end, execution_thread)| *end == 0 && *execution_thread == thread)
+                        .expect("exiting a span which was not entered on this thread");
+                    interval.1 = time;
+                    assert_eq!(thread_active_spans.pop(), Some(id));
+                    let (enter_allocations, children_allocations) =
+                        allocations_stack.pop().unwrap();
+                    let allocations = exit_allocations - enter_allocations;
+                    span.allocations += allocations - children_allocations;
+                    if let Some((_, parent_children_allocations)) = allocations_stack.last_mut() {
+                        *parent_children_allocations += allocations;
+                    }
+                    exit_allocations = Allocations::default();
+                    let (enter_scheduling, changed_core) = scheduling_stack.pop().unwrap();
+                    if let Some(exit_scheduling) = exit_scheduling.take() {
+                        span.scheduling
+                            .get_or_insert_with(|| Scheduling {
+                                start_cpu: enter_scheduling[0],
+                                ..Default::default()
+                            })
+                            .add(&enter_scheduling, &exit_scheduling, changed_core);
+                    }
+                    let (enter_perf, children_perf) = perf_stack.pop().unwrap();
+                    let perf = PerfCounters::between(&enter_perf, &exit_perf);
+                    span.perf.add_exclusive(&perf, &children_perf);
+                    if let Some((_, parent_children_perf)) = perf_stack.last_mut() {
+                        parent_children_perf.add(&perf);
+                    }
+                    exit_perf = [None; PERF_COUNTERS];
+                    min_time = min_time.min(time);
+                    max_time = max_time.max(time);
                 }
-                RawEvent::StrField(id, field_name, value) => {
-                    if field_name == &"label" {
-                        spans.get_mut(id).unwrap().name = value
+                // counters logged when exiting spans entered before a previous extraction
+                DecodedEvent::Allocations(id, _, _, _)
+                | DecodedEvent::Scheduling(id, _, _, _)
+                | DecodedEvent::Perf(id, _, _, _)
+                    if thread_active_spans.last() != Some(&id) => {}
+                DecodedEvent::Allocations(_, exiting, counter, value) => {
+                    let allocations = if exiting {
+                        &mut exit_allocations
                     } else {
-                        eprintln!("discarded field : {}", field_name)
+                        &mut allocations_stack.last_mut().unwrap().0
+                    };
+                    *allocations.counter_mut(counter) = value;
+                }
+                DecodedEvent::Scheduling(_, exiting, counter, value) => {
+                    if counter == 0 && last_cpu.replace(value).is_some_and(|cpu| cpu != value) {
+                        // the core changed since the previous sample, while all spans active
+                        // before this event were entered (an entered span is already active)
+                        let entered = scheduling_stack.len() - !exiting as usize;
+                        for (_, changed_core) in &mut scheduling_stack[..entered] {
+                            *changed_core = true
+                        }
+                    }
+                    let counters = if exiting {
+                        exit_scheduling.get_or_insert([0; SCHEDULING_COUNTERS])
+                    } else {
+                        &mut scheduling_stack.last_mut().unwrap().0
+                    };
+                    counters[counter as usize] = value;
+                }
+                DecodedEvent::Perf(_, exiting, counter, value) => {
+                    let counters = if exiting {
+                        &mut exit_perf
+                    } else {
+                        &mut perf_stack.last_mut().unwrap().0
+                    };
+                    counters[counter as usize] = Some(value);
+                }
+                #[cfg(feature = "log")]
+                DecodedEvent::Log(index) => {
+                    let record = records.get_mut(index).and_then(Option::take);
+                    // records logged outside of any span are discarded
+                    if let (Some(id), Some(mut record)) = (thread_active_spans.last(), record) {
+                        // a plain conversion: records are not timed events
+                        record.time = to_nanos(record.time as u64);
+                        spans.get_mut(id).unwrap().logs.push(record)
+                    }
+                }
+                // fields recorded after a previous extraction took their span are lost
+                DecodedEvent::StrField(id, _, _) if !spans.contains_key(&id) => {}
+                DecodedEvent::StrField(id, field_name, value) => {
+                    let span = spans.get_mut(&id).unwrap();
+                    let kind = if field_name == KIND_FIELD {
+                        SpanKind::from_field(value)
+                    } else {
+                        None
+                    };
+                    if field_name == "label" {
+                        span.name = value
+                    } else if let Some(kind) = kind {
+                        span.kind = kind
+                    } else {
+                        // unknown kinds are kept as regular fields
+                        span.fields.push((field_name, value))
                     }
                 }
             }
@@ -87,16 +454,21 @@ pub(super) fn extract_spans() -> HashMap<u64, Span> {
 
         all_active_spans.append(&mut thread_active_spans);
 
-        log.reset();
+        // logs of finished threads are freed below
+        if !finished {
+            log.reset();
+        }
     }
+    // all events of finished threads are extracted: we can free their logs
+    LOGS.remove_if(|log| finished_logs.contains(&Arc::as_ptr(log)));
 
     let unfinished_spans = all_active_spans.into_iter().fold(0, |count, id| {
         let span = spans
             .get_mut(&id)
             .expect("Span should be in the hashmap already.");
 
-        if span.end == 0 {
-            span.end = max_time;
+        if let Some(interval) = span.intervals.iter_mut().find(|(_, end, _)| *end == 0) {
+            interval.1 = max_time;
             count + 1
         } else {
             count
@@ -104,12 +476,211 @@ pub(super) fn extract_spans() -> HashMap<u64, Span> {
     });
 
     assert_eq!(entered_count, exited_count + unfinished_spans);
-    assert_eq!(entered_count, spans.len());
-    // now translate times
+    // spans never entered while recording (see `pause`) are not part of the trace:
+    // their children are attached to their closest recorded ancestor
+    let unentered: HashMap<u64, Option<u64>> = spans
+        .values()
+        .filter(|s| s.intervals.is_empty())
+        .map(|s| (s.id, s.parent))
+        .collect();
+    spans.retain(|id, _| !unentered.contains_key(id));
+    spans.values_mut().for_each(|s| {
+        while let Some(parent) = s.parent.and_then(|parent| unentered.get(&parent)) {
+            s.parent = *parent
+        }
+    });
+    // now translate times: spans go from their first enter to their last exit
     spans.values_mut().for_each(|s| {
-        s.start -= min_time;
-        s.end -= min_time;
+        s.intervals.iter_mut().for_each(|interval| {
+            interval.0 -= min_time;
+            interval.1 -= min_time;
+        });
+        s.intervals.sort_unstable();
+        s.start = s.intervals[0].0;
+        s.end = s.intervals.iter().map(|interval| interval.1).max().unwrap();
+        s.execution_thread = s.intervals[0].2;
+        s.logs.iter_mut().for_each(|r| r.time -= min_time);
     });
 
-    spans
+    // nothing was recorded: there is no offset
+    let offset = if spans.is_empty() { 0 } else { min_time };
+    (offset, spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    use tracing::Instrument;
+
+    /// A future pending on its first poll.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, _context: &mut Context) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        unsafe { Waker::from_raw(clone(std::ptr::null())) }
+    }
+
+    fn poll<F: Future>(future: &mut Pin<Box<F>>) -> Poll<F::Output> {
+        let waker = noop_waker();
+        future.as_mut().poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn repeated_extractions_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        let dispatch = tracing::Dispatch::new(super::super::FastSubscriber::new());
+        tracing::dispatcher::with_default(&dispatch, || {
+            let open = tracing::span!(tracing::Level::TRACE, "open", size = tracing::field::Empty);
+            let entered = open.enter();
+            let spans = extract_spans();
+            assert_eq!(spans.len(), 1);
+            open.record("size", &3);
+            drop(entered);
+            tracing::span!(tracing::Level::TRACE, "after").in_scope(|| ());
+        });
+        let spans = extract_spans();
+        assert!(spans.values().map(|s| s.name).eq(["after"]));
+    }
+    #[test]
+    fn polls_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        let dispatch = tracing::Dispatch::new(super::super::FastSubscriber::new());
+        let mut future = tracing::dispatcher::with_default(&dispatch, || {
+            Box::pin(YieldOnce(false).instrument(tracing::span!(tracing::Level::TRACE, "future")))
+        });
+        // first poll here, second poll on another thread
+        let first_poll = tracing::dispatcher::with_default(&dispatch, || poll(&mut future));
+        assert_eq!(first_poll, Poll::Pending);
+        let second_dispatch = dispatch.clone();
+        let second_poll = std::thread::spawn(move || {
+            let second_poll =
+                tracing::dispatcher::with_default(&second_dispatch, || poll(&mut future));
+            drop(future);
+            second_poll
+        })
+        .join()
+        .unwrap();
+        assert_eq!(second_poll, Poll::Ready(()));
+
+        let spans = extract_spans();
+        let span = spans.values().find(|s| s.name == "future").unwrap();
+        // recent tracing versions also enter the span when dropping the future
+        assert!(span.intervals.len() >= 2);
+        assert_ne!(span.intervals[0].2, span.intervals[1].2);
+        assert_eq!(
+            span.busy_time() + span.suspended_time(),
+            span.end - span.start
+        );
+    }
+    #[test]
+    fn migrations_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        let scheduled = |event: fn(u64, u64) -> RawEvent, id, exiting, cpu| {
+            if exiting {
+                log_event(RawEvent::scheduling(id, true, 0, cpu));
+                log_event(event(id, 1));
+            } else {
+                log_event(event(id, 1));
+                log_event(RawEvent::scheduling(id, false, 0, cpu));
+            }
+        };
+        for id in 1..4 {
+            log_event(RawEvent::new_span(id, "migrations", 0))
+        }
+        // the parent starts and ends on core 0 but its first child runs on core 1
+        scheduled(RawEvent::enter, 1, false, 0);
+        scheduled(RawEvent::enter, 2, false, 1);
+        scheduled(RawEvent::exit, 2, true, 1);
+        scheduled(RawEvent::enter, 3, false, 0);
+        scheduled(RawEvent::exit, 3, true, 0);
+        scheduled(RawEvent::exit, 1, true, 0);
+        let spans = extract_spans();
+        let migrated = |id| spans[&id].scheduling.unwrap().migrated();
+        assert!(migrated(1));
+        assert!(!migrated(2));
+        assert!(!migrated(3));
+    }
+    #[cfg(feature = "perf")]
+    #[test]
+    fn exclusive_counters_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        // allocated bytes and instructions both grow by 10 in the parent, 20 in the child
+        let counted = |event: fn(u64, u64) -> RawEvent, id, exiting, value| {
+            let log_counters = || {
+                log_event(RawEvent::allocations(id, exiting, 1, value));
+                log_event(RawEvent::perf(id, exiting, 4, value));
+            };
+            if exiting {
+                log_counters();
+                log_event(event(id, 1));
+            } else {
+                log_event(event(id, 1));
+                log_counters();
+            }
+        };
+        for id in 1..3 {
+            log_event(RawEvent::new_span(id, "counters", 0))
+        }
+        counted(RawEvent::enter, 1, false, 100);
+        counted(RawEvent::enter, 2, false, 105);
+        counted(RawEvent::exit, 2, true, 125);
+        counted(RawEvent::exit, 1, true, 130);
+        let spans = extract_spans();
+        for (id, value) in [(1, 10), (2, 20)] {
+            assert_eq!(spans[&id].allocations.allocated, value);
+            assert_eq!(
+                spans[&id].perf.available().collect::<Vec<_>>(),
+                [("instructions", value)]
+            );
+        }
+    }
+    #[test]
+    fn compact_events_test() {
+        assert!(std::mem::size_of::<RawEvent>() <= 16);
+        let events = [
+            RawEvent::new_span(3, "task", 2),
+            RawEvent::str_field(3, "label", &String::from("dynamic")),
+            RawEvent::enter(3, 42),
+            RawEvent::exit(3, u64::MAX),
+        ];
+        let strings = strings();
+        match events[0].decode(&strings) {
+            DecodedEvent::NewSpan(3, "task", 2) => (),
+            _ => panic!("wrong new span"),
+        }
+        match events[1].decode(&strings) {
+            DecodedEvent::StrField(3, "label", "dynamic") => (),
+            _ => panic!("wrong field"),
+        }
+        match events[2].decode(&strings) {
+            DecodedEvent::Enter(3, 42) => (),
+            _ => panic!("wrong enter"),
+        }
+        match events[3].decode(&strings) {
+            DecodedEvent::Exit(3, u64::MAX) => (),
+            _ => panic!("wrong exit"),
+        }
+    }
 }
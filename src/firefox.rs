@@ -0,0 +1,296 @@
+//! Export recorded spans in the Firefox Profiler's processed profile format.
+//! Each thread becomes a profiler thread with one interval marker per span.
+//! Since we do not sample, we generate a synthetic samples table
+//! from the stacks of spans active at regular time intervals.
+use super::json::{json_array, json_string};
+use super::spans::{thread_states, THREAD_NAME_FIELD};
+use super::{Span, Trace};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Minimal time between two synthetic samples (in nano seconds).
+const MIN_SAMPLING_INTERVAL: u128 = 1_000;
+/// Maximal number of synthetic samples per thread.
+const MAX_SAMPLES: u128 = 100_000;
+/// Version of the processed profile format we write.
+const PROCESSED_PROFILE_VERSION: u32 = 47;
+/// Marker phase for markers with a start and an end.
+const INTERVAL_MARKER: u32 = 1;
+
+/// Convert nano seconds to the profiler's milliseconds.
+fn milliseconds(nano: u128) -> f64 {
+    nano as f64 / 1_000_000.0
+}
+
+/// All tables of one profiler thread.
+#[derive(Default)]
+struct ProfileThread {
+    strings: Vec<&'static str>,
+    string_indices: HashMap<&'static str, usize>,
+    // we have exactly one frame for each function so they share indices
+    functions: Vec<usize>,
+    function_indices: HashMap<&'static str, usize>,
+    stack_prefixes: Vec<Option<usize>>,
+    stack_frames: Vec<usize>,
+    stack_indices: HashMap<(Option<usize>, usize), usize>,
+    samples_stacks: Vec<Option<usize>>,
+    samples_times: Vec<u128>,
+}
+
+impl ProfileThread {
+    fn new(thread_spans: &[&Span], end: u128, sampling_interval: u128) -> Self {
+        let mut thread = ProfileThread::default();
+        for span in thread_spans {
+            thread.string(span.name);
+        }
+        let mut states = thread_states(thread_spans).into_iter().peekable();
+        let mut current_stack: Vec<usize> = Vec::new();
+        let mut time = 0;
+        while time <= end {
+            while let Some((_, state)) = states.next_if(|(state_time, _)| *state_time <= time) {
+                match state {
+                    Some(name) => {
+                        let stack = thread.stack(current_stack.last().cloned(), name);
+                        current_stack.push(stack)
+                    }
+                    None => {
+                        current_stack.pop();
+                    }
+                }
+            }
+            thread.samples_stacks.push(current_stack.last().cloned());
+            thread.samples_times.push(time);
+            time += sampling_interval;
+        }
+        thread
+    }
+    fn string(&mut self, s: &'static str) -> usize {
+        let strings = &mut self.strings;
+        *self.string_indices.entry(s).or_insert_with(|| {
+            strings.push(s);
+            strings.len() - 1
+        })
+    }
+    fn function(&mut self, name: &'static str) -> usize {
+        let name_index = self.string(name);
+        let functions = &mut self.functions;
+        *self.function_indices.entry(name).or_insert_with(|| {
+            functions.push(name_index);
+            functions.len() - 1
+        })
+    }
+    fn stack(&mut self, prefix: Option<usize>, name: &'static str) -> usize {
+        let frame = self.function(name);
+        let prefixes = &mut self.stack_prefixes;
+        let frames = &mut self.stack_frames;
+        *self
+            .stack_indices
+            .entry((prefix, frame))
+            .or_insert_with(|| {
+                prefixes.push(prefix);
+                frames.push(frame);
+                prefixes.len() - 1
+            })
+    }
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        thread_id: usize,
+        thread_spans: &[&Span],
+    ) -> std::io::Result<()> {
+        let null_or = |i: &Option<usize>| {
+            i.map(|i| i.to_string())
+                .unwrap_or_else(|| "null".to_owned())
+        };
+        let functions = self.functions.len();
+        let stacks = self.stack_frames.len();
+        let markers = thread_spans.len();
+        write!(
+            writer,
+            "{{\"processType\":\"default\",\"processStartupTime\":0,\"processShutdownTime\":null,\
+             \"registerTime\":0,\"unregisterTime\":null,\"pausedRanges\":[],\
+             \"name\":{name},\"isMainThread\":{main},\"pid\":\"0\",\"tid\":{id},",
+            id = thread_id,
+            name = json_string(&thread_name(thread_id, thread_spans)),
+            main = thread_id == 0
+        )?;
+        write!(
+            writer,
+            "\"samples\":{{\"length\":{},\"stack\":{},\"time\":{},\"weight\":null,\"weightType\":\"samples\"}},",
+            self.samples_times.len(),
+            json_array(self.samples_stacks.iter().map(null_or)),
+            json_array(self.samples_times.iter().map(|t| milliseconds(*t))),
+        )?;
+        write!(
+            writer,
+            "\"markers\":{{\"length\":{},\"category\":{},\"data\":{},\"endTime\":{},\"name\":{},\"phase\":{},\"startTime\":{}}},",
+            markers,
+            json_array(std::iter::repeat_n(0, markers)),
+            json_array(std::iter::repeat_n("null", markers)),
+            json_array(thread_spans.iter().map(|s| milliseconds(s.end))),
+            json_array(thread_spans.iter().map(|s| self.string_indices[s.name])),
+            json_array(std::iter::repeat_n(INTERVAL_MARKER, markers)),
+            json_array(thread_spans.iter().map(|s| milliseconds(s.start))),
+        )?;
+        write!(
+            writer,
+            "\"stackTable\":{{\"length\":{},\"prefix\":{},\"frame\":{},\"category\":{},\"subcategory\":{}}},",
+            stacks,
+            json_array(self.stack_prefixes.iter().map(null_or)),
+            json_array(&self.stack_frames),
+            json_array(std::iter::repeat_n(0, stacks)),
+            json_array(std::iter::repeat_n(0, stacks)),
+        )?;
+        write!(
+            writer,
+            "\"frameTable\":{{\"length\":{len},\"address\":{minus_ones},\"inlineDepth\":{zeros},\
+             \"category\":{zeros},\"subcategory\":{zeros},\"func\":{func},\"nativeSymbol\":{nulls},\
+             \"innerWindowID\":{nulls},\"implementation\":{nulls},\"line\":{nulls},\"column\":{nulls}}},",
+            len = functions,
+            minus_ones = json_array(std::iter::repeat_n(-1, functions)),
+            zeros = json_array(std::iter::repeat_n(0, functions)),
+            func = json_array(0..functions),
+            nulls = json_array(std::iter::repeat_n("null", functions)),
+        )?;
+        write!(
+            writer,
+            "\"funcTable\":{{\"length\":{len},\"name\":{names},\"isJS\":{falses},\"relevantForJS\":{falses},\
+             \"resource\":{minus_ones},\"fileName\":{nulls},\"lineNumber\":{nulls},\"columnNumber\":{nulls}}},",
+            len = functions,
+            names = json_array(&self.functions),
+            falses = json_array(std::iter::repeat_n(false, functions)),
+            minus_ones = json_array(std::iter::repeat_n(-1, functions)),
+            nulls = json_array(std::iter::repeat_n("null", functions)),
+        )?;
+        write!(
+            writer,
+            "\"resourceTable\":{{\"length\":0,\"lib\":[],\"name\":[],\"host\":[],\"type\":[]}},\
+             \"nativeSymbols\":{{\"length\":0,\"libIndex\":[],\"address\":[],\"name\":[],\"functionSize\":[]}},\
+             \"stringArray\":{}}}",
+            json_array(self.strings.iter().map(|s| json_string(s)))
+        )
+    }
+}
+
+/// Name of the thread (given when spawning it) or its number.
+fn thread_name(thread_id: usize, thread_spans: &[&Span]) -> String {
+    thread_spans
+        .iter()
+        .filter(|s| !s.is_stolen())
+        .flat_map(|s| s.fields.iter())
+        .find(|(name, _)| *name == THREAD_NAME_FIELD)
+        .map(|(_, value)| value.to_string())
+        .unwrap_or_else(|| format!("thread {}", thread_id))
+}
+
+/// Write given spans as a processed profile.
+/// `origin` is the unix time (in nano seconds) of time 0 in the spans.
+pub(super) fn write_firefox_profile<W: Write>(
+    writer: &mut W,
+    spans: &HashMap<u64, Span>,
+    origin: u128,
+) -> std::io::Result<()> {
+    let end = spans.values().map(|s| s.end).max().unwrap_or(0);
+    let sampling_interval = (end / MAX_SAMPLES).max(MIN_SAMPLING_INTERVAL);
+    write!(
+        writer,
+        "{{\"meta\":{{\"interval\":{},\"startTime\":{},\"processType\":0,\"product\":\"fast-tracer\",\
+         \"stackwalk\":0,\"version\":27,\"preprocessedProfileVersion\":{},\"symbolicated\":true,\
+         \"categories\":[{{\"name\":\"Other\",\"color\":\"grey\",\"subcategories\":[\"Other\"]}}],\
+         \"markerSchema\":[]}},\"libs\":[],\"pages\":[],\"counters\":[],\"threads\":[",
+        milliseconds(sampling_interval),
+        milliseconds(origin),
+        PROCESSED_PROFILE_VERSION
+    )?;
+    let spans_per_thread = spans
+        .values()
+        .sorted_by_key(|s| s.start)
+        .into_group_map_by(|s| s.execution_thread);
+    for (index, (thread_id, thread_spans)) in spans_per_thread
+        .iter()
+        .sorted_by_key(|(thread_id, _)| **thread_id)
+        .enumerate()
+    {
+        if index != 0 {
+            write!(writer, ",")?;
+        }
+        ProfileThread::new(thread_spans, end, sampling_interval).write(
+            writer,
+            *thread_id,
+            thread_spans,
+        )?;
+    }
+    writeln!(writer, "]}}")
+}
+
+/// Records the execution of `op` and saves it as a Firefox Profiler
+/// processed profile (to be loaded in https://profiler.firefox.com).
+pub fn firefox_profile<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(
+    path: P,
+    op: F,
+) -> std::io::Result<R> {
+    let (r, trace) = Trace::record(op);
+    trace.save_firefox_profile(path)?;
+    Ok(r)
+}
+
+/// Shortcut for `Trace::extract().save_firefox_profile(path)`.
+pub fn dump_firefox_profile<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_firefox_profile(path)
+}
+
+#[cfg(all(test, feature = "chrome"))]
+mod tests {
+    use super::super::events::RECORDING_TESTS;
+    use super::*;
+    use tracing::{span, Level};
+
+    #[test]
+    fn firefox_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        let ((), trace) = Trace::record(|| {
+            span!(Level::TRACE, "outer").in_scope(|| {
+                span!(Level::TRACE, "inner").in_scope(|| ());
+                std::thread::spawn(|| span!(Level::TRACE, "spawned").in_scope(|| ()))
+                    .join()
+                    .unwrap()
+            })
+        });
+        let mut profile = Vec::new();
+        write_firefox_profile(&mut profile, &trace.spans, 1_000_000).unwrap();
+        let profile: serde_json::Value = serde_json::from_slice(&profile).unwrap();
+        assert_eq!(profile["meta"]["startTime"], 1.0);
+        let threads = profile["threads"].as_array().unwrap();
+        assert_eq!(threads.len(), 2);
+        // names of the markers of each thread, read through its string table
+        let markers = threads
+            .iter()
+            .map(|thread| {
+                let strings = thread["stringArray"].as_array().unwrap();
+                let markers = &thread["markers"];
+                let names = markers["name"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|index| strings[index.as_u64().unwrap() as usize].as_str().unwrap())
+                    .sorted()
+                    .collect::<Vec<_>>();
+                assert_eq!(markers["length"], names.len());
+                assert!(markers["phase"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .all(|phase| *phase == INTERVAL_MARKER));
+                // each name is interned once
+                assert_eq!(strings.iter().unique().count(), strings.len());
+                names
+            })
+            .sorted_by_key(|names| names.len())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            markers,
+            vec![vec!["spawned"], vec!["inner", "main_task", "outer"]]
+        );
+    }
+}
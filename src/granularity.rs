@@ -0,0 +1,198 @@
+//! Leaf tasks granularity analysis.
+//! We compute a log-scale histogram of leaf tasks durations for each span name
+//! and report how many of them are too small to be worth parallelising.
+use super::overhead::calibrate;
+use super::svg::{time_string, COLORS};
+use super::{Span, Trace, SVG_HEIGHT, SVG_WIDTH};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+/// Log-scale histogram of durations.
+/// Bucket `i` counts the tasks lasting between 2^i and 2^(i+1) nano seconds.
+#[derive(Debug)]
+pub(super) struct Histogram {
+    pub(super) name: &'static str,
+    pub(super) buckets: Vec<usize>,
+    pub(super) durations: Vec<u128>,
+}
+
+impl Histogram {
+    fn new(name: &'static str, durations: Vec<u128>) -> Self {
+        let mut buckets = Vec::new();
+        for duration in &durations {
+            let bucket = bucket(*duration);
+            if bucket >= buckets.len() {
+                buckets.resize(bucket + 1, 0);
+            }
+            buckets[bucket] += 1;
+        }
+        Histogram {
+            name,
+            buckets,
+            durations,
+        }
+    }
+    /// How many tasks last strictly less than given time.
+    pub(super) fn count_below(&self, time: u128) -> usize {
+        self.durations.iter().filter(|&&d| d < time).count()
+    }
+}
+
+/// In which bucket should we store given duration.
+fn bucket(duration: u128) -> usize {
+    (128 - duration.leading_zeros() as usize).saturating_sub(1)
+}
+
+/// Compute for each span name the histogram of its leaf tasks durations.
+/// A leaf task is a span which is the parent of no other span.
+pub(super) fn leaf_histograms(spans: &HashMap<u64, Span>) -> Vec<Histogram> {
+    let parents: HashSet<u64> = spans.values().filter_map(|s| s.parent).collect();
+    spans
+        .values()
+        .filter(|s| !parents.contains(&s.id))
+        .map(|s| (s.name, s.end - s.start))
+        .into_group_map()
+        .into_iter()
+        .sorted_by_key(|(name, _)| *name)
+        .map(|(name, durations)| Histogram::new(name, durations))
+        .collect()
+}
+
+/// Records the execution of `op`, prints for each span name how many
+/// leaf tasks last less than `threshold` nano seconds
+/// (and less than the tracer's own overhead) and saves
+/// the histograms of leaf tasks durations as an svg file.
+/// This is useful to choose rayon's `with_min_len` or adaptive split thresholds.
+pub fn granularity_svg<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(
+    path: P,
+    threshold: u128,
+    op: F,
+) -> std::io::Result<R> {
+    let overhead = calibrate().per_span();
+    let (r, Trace { spans, .. }) = Trace::record(op);
+    let histograms = leaf_histograms(&spans);
+    println!(
+        "tracing overhead: {} per span, threshold: {}",
+        time_string(overhead),
+        time_string(threshold)
+    );
+    for histogram in &histograms {
+        let count = histogram.durations.len();
+        let small = histogram.count_below(threshold);
+        let tiny = histogram.count_below(overhead);
+        println!(
+            "{}: {} leaf tasks, {} below threshold ({}%), {} below overhead ({}%)",
+            histogram.name,
+            count,
+            small,
+            (small as f64 / count as f64) * 100.0,
+            tiny,
+            (tiny as f64 / count as f64) * 100.0,
+        );
+    }
+    save_histograms_svg(path, &histograms, threshold, overhead)?;
+    Ok(r)
+}
+
+fn save_histograms_svg<P: AsRef<std::path::Path>>(
+    path: P,
+    histograms: &[Histogram],
+    threshold: u128,
+    overhead: u128,
+) -> std::io::Result<()> {
+    let mut svg_file = std::fs::File::create(path)?;
+    writeln!(
+        &mut svg_file,
+        "<svg version='1.1' viewBox='0 0 {} {}' xmlns='http://www.w3.org/2000/svg'>",
+        SVG_WIDTH, SVG_HEIGHT
+    )?;
+    // keep some space at the bottom for the time axis
+    let axis_height = 40.0;
+    let buckets_number = histograms
+        .iter()
+        .map(|h| h.buckets.len())
+        .max()
+        .unwrap_or(0)
+        .max(bucket(threshold) + 1)
+        .max(bucket(overhead) + 1);
+    let bucket_width = SVG_WIDTH as f64 / buckets_number as f64;
+    let row_height = (SVG_HEIGHT as f64 - axis_height) / histograms.len().max(1) as f64;
+    for (row, histogram) in histograms.iter().enumerate() {
+        let y = row as f64 * row_height;
+        let max_count = histogram.buckets.iter().max().cloned().unwrap_or(1) as f64;
+        for (index, count) in histogram.buckets.iter().enumerate() {
+            let height = *count as f64 / max_count * row_height * 0.8;
+            writeln!(
+                &mut svg_file,
+                "<rect width='{}' height='{}' x='{}' y='{}' fill='{}'><title>{} tasks</title></rect>",
+                bucket_width * 0.9,
+                height,
+                index as f64 * bucket_width,
+                y + row_height - height,
+                COLORS[row % COLORS.len()],
+                count
+            )?;
+        }
+        writeln!(
+            &mut svg_file,
+            "<text x='5' y='{}'>{} ({} leaf tasks)</text>",
+            y + 20.0,
+            histogram.name,
+            histogram.durations.len()
+        )?;
+    }
+    for index in 0..buckets_number {
+        writeln!(
+            &mut svg_file,
+            "<text x='{}' y='{}'>{}</text>",
+            index as f64 * bucket_width,
+            SVG_HEIGHT as f64 - axis_height / 3.0,
+            time_string(1 << index)
+        )?;
+    }
+    for (time, color, label) in &[
+        (threshold, "red", "threshold"),
+        (overhead, "black", "overhead"),
+    ] {
+        let x = log_position(*time) * bucket_width;
+        writeln!(
+            &mut svg_file,
+            "<line x1='{}' y1='0' x2='{}' y2='{}' stroke='{}' stroke-width='3' stroke-dasharray='10'><title>{} {}</title></line>",
+            x,
+            x,
+            SVG_HEIGHT as f64 - axis_height,
+            color,
+            label,
+            time_string(*time)
+        )?;
+    }
+    writeln!(&mut svg_file, "</svg>")?;
+    Ok(())
+}
+
+/// Position of given time on the log-scale axis (in buckets).
+fn log_position(time: u128) -> f64 {
+    if time == 0 {
+        0.0
+    } else {
+        (time as f64).log2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn buckets_test() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 0);
+        assert_eq!(bucket(2), 1);
+        assert_eq!(bucket(3), 1);
+        assert_eq!(bucket(1024), 10);
+        let histogram = Histogram::new("leaf", vec![1, 3, 2, 1024]);
+        assert_eq!(histogram.buckets[..3], [1, 2, 0]);
+        assert_eq!(histogram.buckets[10], 1);
+        assert_eq!(histogram.count_below(3), 2);
+    }
+}
@@ -1,3 +1,4 @@
+use super::spans::SpanKind;
 use super::{Span, SVG_HEIGHT, SVG_WIDTH};
 use either::Either;
 use itertools::Itertools;
@@ -20,7 +21,10 @@ pub(super) struct Node {
 }
 
 impl Node {
-    fn new_from_children<I: Iterator<Item = Node>>(children: I, is_parallel: bool) -> Self {
+    pub(super) fn new_from_children<I: Iterator<Item = Node>>(
+        children: I,
+        is_parallel: bool,
+    ) -> Self {
         let mut size = [0, 0];
         // let's compute dimensions and collect children in one pass
         let children_vec = if is_parallel {
@@ -48,7 +52,7 @@ impl Node {
             position: [0.0; 2],
         }
     }
-    fn new_from_task(task: Task) -> Self {
+    pub(super) fn new_from_task(task: Task) -> Self {
         let width = task.end - task.start;
         Node {
             children: Either::Right(task),
@@ -130,13 +134,25 @@ impl Graph {
             children.sort_by_key(|child_id| (spans[child_id].name, spans[child_id].start));
         });
 
-        assert_eq!(roots.len(), 1); // TODO: for now
-        let root_id = roots.first().unwrap();
-        assert_eq!(spans[root_id].name, "main_task");
-        let root = build_graph(root_id, &children, spans);
-        let x_scale = root.size[0] as f64 / SVG_WIDTH as f64;
+        // independent roots (spans without parents, for example on spawned threads)
+        // are displayed side by side, by starting order
+        roots.sort_by_key(|root_id| spans[root_id].start);
+        let mut subgraphs = roots
+            .iter()
+            .map(|root_id| build_graph(root_id, &children, spans));
+        let root = if roots.len() == 1 {
+            subgraphs.next().unwrap()
+        } else {
+            // no roots (nothing recorded) gives an empty root
+            Node::new_from_children(subgraphs, true)
+        };
+        if spans.is_empty() {
+            start = end;
+        }
+        // empty graphs (or graphs of empty tasks) have a null size: avoid dividing by 0
+        let x_scale = root.size[0].max(1) as f64 / SVG_WIDTH as f64;
         // add some extra space at bottom to display threads idling
-        let y_scale = (root.size[1] + max_thread as u128) as f64 / SVG_HEIGHT as f64;
+        let y_scale = (root.size[1] + max_thread as u128).max(1) as f64 / SVG_HEIGHT as f64;
         let mut graph = Graph {
             root,
             start,
@@ -158,23 +174,47 @@ fn build_graph(
     children: &HashMap<u64, Vec<u64>>,
     spans: &HashMap<u64, Span>,
 ) -> Node {
-    let subgraphs = children
-        .get(root_id)
-        .into_iter()
-        .flatten()
+    let mut children_ids = children.get(root_id).cloned().unwrap_or_default();
+    let is_parallel = spans[root_id].kind.is_parallel();
+    if !is_parallel {
+        // sequential children (like different kinds of spawns) must be in time order
+        children_ids.sort_by_key(|child_id| spans[child_id].start);
+    }
+    let subgraphs = children_ids
+        .iter()
         .map(|child_id| build_graph(child_id, children, spans));
-    if spans[root_id].name == "parallel" {
+    let root_span = &spans[root_id];
+    if root_span.kind == SpanKind::Join && !children_ids.is_empty() {
+        // own work ; parallel children ; own work
+        let first_start = children_ids.iter().map(|id| spans[id].start).min().unwrap();
+        let last_end = children_ids.iter().map(|id| spans[id].end).max().unwrap();
+        let own_task = |start: u128, end: u128| {
+            Node::new_from_task(Task {
+                start,
+                end: end.max(start),
+                thread: root_span.execution_thread,
+                label: root_span.name,
+            })
+        };
+        let parallel = Node::new_from_children(subgraphs, true);
+        Node::new_from_children(
+            vec![
+                own_task(root_span.start, first_start),
+                parallel,
+                own_task(last_end, root_span.end),
+            ]
+            .into_iter(),
+            false,
+        )
+    } else if is_parallel {
         // parallel display
         Node::new_from_children(subgraphs, true)
     } else {
         // sequential display
         // we interleave "fake" tasks between the real children
-        let times = children
-            .get(root_id)
-            .into_iter()
-            .flatten()
+        let times = children_ids
+            .iter()
             .map(|child_id| (spans[child_id].start, spans[child_id].end));
-        let root_span = &spans[root_id];
         let all_times = std::iter::once((0, root_span.start))
             .chain(times)
             .chain(std::iter::once((root_span.end, 0)));
@@ -191,3 +231,137 @@ fn build_graph(
         Node::new_from_children(tasks.interleave(subgraphs), false)
     }
 }
+
+/// Tasks dependencies extracted from the series-parallel graph.
+/// Edges follow the same entry/exit points logic as the svg display.
+pub(super) struct Dag<'a> {
+    pub(super) tasks: Vec<&'a Task>,
+    pub(super) successors: Vec<Vec<usize>>,
+    pub(super) predecessors_number: Vec<usize>,
+}
+
+impl<'a> Dag<'a> {
+    pub(super) fn new(root: &'a Node) -> Self {
+        let mut dag = Dag {
+            tasks: Vec::new(),
+            successors: Vec::new(),
+            predecessors_number: Vec::new(),
+        };
+        dag.add_node(root);
+        dag
+    }
+    /// Add all tasks of given node, returning its entry and exit tasks.
+    fn add_node(&mut self, node: &'a Node) -> (Vec<usize>, Vec<usize>) {
+        match &node.children {
+            Either::Right(task) => {
+                let index = self.tasks.len();
+                self.tasks.push(task);
+                self.successors.push(Vec::new());
+                self.predecessors_number.push(0);
+                (vec![index], vec![index])
+            }
+            Either::Left(children) => {
+                let mut entries = Vec::new();
+                let mut exits: Vec<usize> = Vec::new();
+                for child in children {
+                    let (child_entries, child_exits) = self.add_node(child);
+                    if node.is_parallel {
+                        entries.extend(child_entries);
+                        exits.extend(child_exits);
+                    } else if !child_entries.is_empty() {
+                        // empty children (without tasks) must not break the chain
+                        if entries.is_empty() {
+                            entries = child_entries;
+                        } else {
+                            for exit in &exits {
+                                for entry in &child_entries {
+                                    self.successors[*exit].push(*entry);
+                                    self.predecessors_number[*entry] += 1;
+                                }
+                            }
+                        }
+                        exits = child_exits;
+                    }
+                }
+                (entries, exits)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn leaf(duration: u128) -> Node {
+        Node::new_from_task(Task {
+            start: 0,
+            end: duration,
+            thread: 0,
+            label: "leaf",
+        })
+    }
+    #[test]
+    fn empty_child_test() {
+        // 1 ; () ; 1 ; () : the second leaf still depends on the first one
+        let empty = || Node::new_from_children(std::iter::empty(), true);
+        let root = Node::new_from_children(
+            vec![empty(), leaf(1), empty(), leaf(1), empty()].into_iter(),
+            false,
+        );
+        let dag = Dag::new(&root);
+        assert_eq!(dag.tasks.len(), 2);
+        assert_eq!(dag.successors, vec![vec![1], vec![]]);
+        assert_eq!(dag.predecessors_number, vec![0, 1]);
+    }
+    #[test]
+    fn kind_field_test() {
+        use super::super::events::{extract_spans, RECORDING_TESTS};
+        use super::super::FastSubscriber;
+        use tracing::{span, Level};
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        // a span with two children, laid out according to its kind
+        let layout = |kind: &'static str| {
+            tracing::subscriber::with_default(FastSubscriber::new(), || {
+                span!(Level::TRACE, "region", fast_tracer.kind = kind).in_scope(|| {
+                    span!(Level::TRACE, "child").in_scope(|| ());
+                    span!(Level::TRACE, "child").in_scope(|| ());
+                })
+            });
+            let graph = Graph::new(&extract_spans());
+            let children = graph.root.children.left().unwrap();
+            (
+                graph.root.is_parallel,
+                children.len(),
+                children.iter().filter(|c| c.is_parallel).count(),
+            )
+        };
+        // own work interleaved with sequential children
+        assert_eq!(layout("task"), (false, 5, 0));
+        // parallel children only
+        assert_eq!(layout("fork"), (true, 2, 0));
+        // own work around parallel children
+        assert_eq!(layout("join"), (false, 3, 1));
+        // unknown kinds are ignored
+        assert_eq!(layout("unknown"), (false, 5, 0));
+        // no name is special by default
+        assert_eq!(super::super::spans::kind_by_name("parallel"), SpanKind::Task);
+    }
+    #[test]
+    fn empty_graph_test() {
+        let graph = Graph::new(&HashMap::new());
+        assert_eq!(graph.end - graph.start, 0);
+        assert!(graph.x_scale.is_finite() && graph.y_scale.is_finite());
+        assert!(graph
+            .root
+            .scaled_size
+            .iter()
+            .chain(&graph.root.position)
+            .all(|x| x.is_finite()));
+        let mut path = std::env::temp_dir();
+        path.push(format!("empty_graph_{}.svg", rand::random::<u64>()));
+        graph.save_svg(&path).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!svg.contains("NaN") && !svg.contains("inf"));
+    }
+}
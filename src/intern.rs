@@ -0,0 +1,152 @@
+//! Interned strings: events only store small ids for span names and fields.
+//!
+//! Each thread caches the ids it already used so that the global table
+//! (behind a lock) is only accessed the first time a thread sees a string.
+//! Non static strings (field values) are copied when first seen and are never freed:
+//! each distinct value costs its length in memory until the end of the program.
+//! Once `STRING_ID_BITS` are exhausted new strings are replaced by `OVERFLOW`.
+use lazy_static::lazy_static;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Number of bits available for string ids in events.
+pub(super) const STRING_ID_BITS: u32 = 21;
+
+/// Replaces all strings once the table is full.
+pub(super) const OVERFLOW: &str = "<overflow>";
+
+/// Size of each thread's cache of static strings ids (a power of two).
+const STATIC_CACHE_SIZE: usize = 256;
+
+pub(super) struct Strings {
+    ids: HashMap<&'static str, u32>,
+    strings: Vec<&'static str>,
+    /// Maximal number of strings.
+    capacity: usize,
+}
+
+impl Default for Strings {
+    fn default() -> Self {
+        Strings::with_capacity(1 << STRING_ID_BITS)
+    }
+}
+
+impl Strings {
+    fn with_capacity(capacity: usize) -> Self {
+        // id 0 is the overflow string
+        Strings {
+            ids: std::iter::once((OVERFLOW, 0)).collect(),
+            strings: vec![OVERFLOW],
+            capacity,
+        }
+    }
+    /// Id of given string (and its static version), registering it if needed.
+    fn register<F: FnOnce() -> &'static str>(
+        &mut self,
+        string: &str,
+        to_static: F,
+    ) -> (&'static str, u32) {
+        if let Some((static_string, id)) = self.ids.get_key_value(string) {
+            return (static_string, *id);
+        }
+        if self.strings.len() >= self.capacity {
+            return (OVERFLOW, 0);
+        }
+        let id = self.strings.len() as u32;
+        let static_string = to_static();
+        self.strings.push(static_string);
+        self.ids.insert(static_string, id);
+        (static_string, id)
+    }
+    /// String with given id.
+    pub(super) fn get(&self, id: u32) -> &'static str {
+        self.strings[id as usize]
+    }
+}
+
+lazy_static! {
+    static ref STRINGS: RwLock<Strings> = RwLock::new(Strings::default());
+}
+
+/// An empty slot of the static strings cache.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Cell<(usize, usize, u32)> = Cell::new((0, 0, 0));
+
+thread_local! {
+    /// Address, length and id of static strings, indexed by address.
+    static STATIC_IDS: [Cell<(usize, usize, u32)>; STATIC_CACHE_SIZE] =
+        const { [EMPTY_SLOT; STATIC_CACHE_SIZE] };
+    /// Ids of the non static strings this thread already interned.
+    static IDS: RefCell<HashMap<&'static str, u32>> = RefCell::new(HashMap::new());
+}
+
+/// Id of given string, registering it in the global table if needed.
+/// Non static strings are copied (and leaked) when first seen.
+/// Returns the static version of the string with its id.
+fn intern_with<F: FnOnce() -> &'static str>(string: &str, to_static: F) -> (&'static str, u32) {
+    if let Some((static_string, id)) = STRINGS.read().unwrap().ids.get_key_value(string) {
+        return (static_string, *id);
+    }
+    // someone might have registered it in between: `register` checks again
+    STRINGS.write().unwrap().register(string, to_static)
+}
+
+/// Id of given static string (like a span or field name).
+pub(super) fn intern_static(string: &'static str) -> u32 {
+    let key = (string.as_ptr() as usize, string.len());
+    let slot = (key.0 >> 3 ^ key.1) & (STATIC_CACHE_SIZE - 1);
+    STATIC_IDS.with(|cache| {
+        let (address, length, id) = cache[slot].get();
+        if (address, length) == key {
+            return id;
+        }
+        let (_, id) = intern_with(string, || string);
+        cache[slot].set((key.0, key.1, id));
+        id
+    })
+}
+
+/// Id of given (field value) string.
+pub(super) fn intern(string: &str) -> u32 {
+    IDS.with(|ids| {
+        if let Some(id) = ids.borrow().get(string) {
+            return *id;
+        }
+        let (static_string, id) =
+            intern_with(string, || Box::leak(string.to_owned().into_boxed_str()));
+        // overflowing strings are not cached: the thread would keep a copy of each of them
+        if id != 0 {
+            ids.borrow_mut().insert(static_string, id);
+        }
+        id
+    })
+}
+
+/// Lock the strings table for decoding events.
+pub(super) fn strings() -> std::sync::RwLockReadGuard<'static, Strings> {
+    STRINGS.read().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn intern_test() {
+        // equal strings at different addresses get the same id
+        let dynamic = String::from("intern_test");
+        let id = intern(&dynamic);
+        assert_eq!(intern_static("intern_test"), id);
+        assert_eq!(intern_static("intern_test"), id);
+        assert_eq!(
+            std::thread::spawn(|| intern("intern_test")).join().unwrap(),
+            id
+        );
+        assert_eq!(strings().get(id), "intern_test");
+        // a full table degrades to the overflow string
+        let mut strings = Strings::with_capacity(2);
+        assert_eq!(strings.register("a", || "a"), ("a", 1));
+        assert_eq!(strings.register("b", || unreachable!()), (OVERFLOW, 0));
+        assert_eq!(strings.register("a", || unreachable!()), ("a", 1));
+    }
+}
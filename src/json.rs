@@ -0,0 +1,37 @@
+//! Minimal helpers for writing json files by hand.
+use itertools::Itertools;
+
+/// Quote and escape given string as a json string.
+pub(super) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write all given values as a json array.
+pub(super) fn json_array<I: IntoIterator>(values: I) -> String
+where
+    I::Item: std::fmt::Display,
+{
+    format!("[{}]", values.into_iter().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn escape_test() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+}
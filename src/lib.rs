@@ -3,56 +3,231 @@ mod list;
 // list of blocks to store events
 mod storage;
 use storage::Storage;
+// time sources
+mod clock;
+#[cfg(target_arch = "x86_64")]
+pub use clock::TscClock;
+pub use clock::{set_clock, Clock, InstantClock};
 // the subscriber used by tracing to record spans and events
 mod subscriber;
-pub use subscriber::{initialize_logger, FastSubscriber};
+pub use subscriber::{initialize_logger, pause, resume, FastSubscriber, RECORDING};
+// interned span names and field values
+mod intern;
+// optional allocations tracking
+mod allocations;
+pub use allocations::TracingAllocator;
+// optional cores and context switches recording
+mod scheduling;
+pub use scheduling::record_scheduling;
+// optional perf counters
+mod perf;
+#[cfg(feature = "perf")]
+pub use perf::record_perf_counters;
+// optional bridge from the log crate
+mod log_bridge;
+#[cfg(feature = "log")]
+pub use log_bridge::{initialize_log_bridge, FastLogger};
+// sampling of hot spans
+mod sampling;
+pub use sampling::{clear_sampling, set_sampling, Sampling};
 // stored events
 mod events;
-use events::{extract_spans, log_event, reset_events, RawEvent};
+use events::{log_event, reset_events, RawEvent};
+// instrumented threads spawning
+pub mod thread;
+// instrumented rayon constructs
+#[cfg(feature = "rayon")]
+pub mod parallel;
 mod spans;
 use spans::Span;
+pub use spans::{clear_span_kinds, set_span_kind, SpanKind};
 mod graph;
 use graph::{Graph, Node, Task};
+// tracing overhead measurement
+mod overhead;
+pub use overhead::{calibrate, compensate_overhead, Overhead};
+// leaf tasks granularity histograms
+mod granularity;
+pub use granularity::granularity_svg;
+// work stealing analysis
+mod steals;
+pub use steals::steals;
+// replay of the tasks graph on virtual processors
+mod simulation;
+pub use simulation::simulate_svg;
+// paje traces export
+mod paje;
+pub use paje::{dump_paje, paje};
+// graphviz export of the tasks graph
+mod dot;
+pub use dot::{dot, dump_dot};
+// opentelemetry export
+mod json;
+mod otlp;
+pub use otlp::{dump_otlp, otlp};
+// firefox profiler export
+mod firefox;
+pub use firefox::{dump_firefox_profile, firefox_profile};
+// flat spans tables export
+mod table;
+#[cfg(feature = "arrow")]
+pub use table::{arrow, dump_arrow};
+pub use table::{csv, dump_csv};
+// recorded or imported traces
+mod trace;
+pub use trace::Trace;
+// assertions on traces for performance tests
+mod assertions;
+#[cfg(feature = "chrome")]
+mod chrome;
 mod svg;
 use itertools::Itertools;
 use std::collections::HashMap;
-pub use svg::{display_svg, gantt_svg, svg};
+pub use svg::{display_svg, dump_gantt_svg, dump_svg, gantt_svg, svg};
 use svg::{SVG_HEIGHT, SVG_WIDTH};
-use tracing::{span, Level};
 
 pub fn stats<R, F: FnOnce() -> R>(op: F) -> R {
-    let subscriber: FastSubscriber = FastSubscriber::new();
-    tracing::subscriber::set_global_default(subscriber).err();
-    reset_events();
-    let span = span!(Level::TRACE, "main_task");
-    let r = {
-        let _enter = span.enter();
-        op()
-    };
-    let spans = extract_spans();
+    let (r, Trace { spans, .. }) = Trace::record(op);
+
+    // each recorded span stands for 1/rate spans of its name, for each sampled ancestor
+    let mut weights: HashMap<u64, f64> = HashMap::new();
+    fn weight(id: u64, spans: &HashMap<u64, Span>, weights: &mut HashMap<u64, f64>) -> f64 {
+        if let Some(weight) = weights.get(&id) {
+            return *weight;
+        }
+        let span = &spans[&id];
+        let parent_weight = span
+            .parent
+            .filter(|parent| spans.contains_key(parent))
+            .map(|parent| weight(parent, spans, weights))
+            .unwrap_or(1.0);
+        let weight = parent_weight / sampling::sampling_rate(span);
+        weights.insert(id, weight);
+        weight
+    }
 
+    // suspended time (between polls of futures) is not counted as work
     let span_hash = spans.values().fold(HashMap::new(), |mut h, s| {
-        h.entry(s.name)
-            .or_insert_with(Vec::new)
-            .push(s.end - s.start);
+        h.entry(s.name).or_insert_with(Vec::new).push((
+            s.busy_time(),
+            s.suspended_time(),
+            weight(s.id, &spans, &mut weights),
+        ));
         h
     });
 
-    let main_duration = span_hash.get("main_task").unwrap()[0];
+    let main_duration = match span_hash.get("main_task") {
+        Some(main_task) => main_task[0].0,
+        None => {
+            println!("nothing was recorded (recording is paused)");
+            return r;
+        }
+    };
 
     for (name, spans) in span_hash
         .into_iter()
         .sorted_by(|(n1, _), (n2, _)| n1.cmp(&n2))
     {
-        let sum = spans.iter().sum::<u128>();
+        let sum = spans.iter().map(|(busy, _, _)| busy).sum::<u128>();
+        let suspended = spans
+            .iter()
+            .map(|(_, suspended, _)| suspended)
+            .sum::<u128>();
         let average = sum / spans.len() as u128;
+        let estimated_count = spans.iter().map(|(_, _, weight)| weight).sum::<f64>();
+        let sampled = spans.iter().any(|(_, _, weight)| *weight != 1.0);
         println!(
-            "{}: {}ns avg ({} spans): {}%, total: {}ns",
+            "{}: {}ns avg ({} spans): {}%, total: {}ns{}{}",
             name,
             average,
             spans.len(),
             (sum as f64 / main_duration as f64) * 100.0,
-            sum
+            sum,
+            if suspended > 0 {
+                format!(", suspended: {}ns", suspended)
+            } else {
+                String::new()
+            },
+            if sampled {
+                let estimated_sum = spans
+                    .iter()
+                    .map(|(busy, _, weight)| *busy as f64 * weight)
+                    .sum::<f64>();
+                format!(
+                    ", sampled: ~{:.0} spans, ~{:.0}ns total ({:.0}%)",
+                    estimated_count,
+                    estimated_sum,
+                    (estimated_sum / main_duration as f64) * 100.0
+                )
+            } else {
+                String::new()
+            }
+        );
+    }
+
+    if allocations::is_tracking() {
+        let allocations_per_name = spans.values().fold(HashMap::new(), |mut h, s| {
+            *h.entry(s.name)
+                .or_insert_with(allocations::Allocations::default) += s.allocations;
+            h
+        });
+        for (name, allocations) in allocations_per_name
+            .into_iter()
+            .sorted_by(|(n1, _), (n2, _)| n1.cmp(n2))
+        {
+            println!(
+                "{}: {} allocations, {} bytes allocated, {} bytes freed",
+                name, allocations.count, allocations.allocated, allocations.freed
+            );
+        }
+    }
+
+    let perf_per_name = spans
+        .values()
+        .filter(|s| s.perf != Default::default())
+        .fold(HashMap::new(), |mut h, s| {
+            h.entry(s.name).or_insert_with(Vec::new).push(s.perf);
+            h
+        });
+    for (name, counters) in perf_per_name
+        .into_iter()
+        .sorted_by(|(n1, _), (n2, _)| n1.cmp(n2))
+    {
+        let totals = counters.iter().fold(HashMap::new(), |mut h, c| {
+            for (counter, value) in c.available() {
+                *h.entry(counter).or_insert(0) += value;
+            }
+            h
+        });
+        println!(
+            "{}: {}",
+            name,
+            perf::PERF_COUNTER_NAMES
+                .iter()
+                .filter_map(|counter| totals.get(counter).map(|total| format!(
+                    "{} {} avg",
+                    counter,
+                    total / counters.len() as u64
+                )))
+                .join(", ")
+        );
+    }
+
+    // each span is entered and exited once: estimate the time the tracer took on each thread
+    let overhead = calibrate();
+    for (thread, thread_spans) in spans
+        .values()
+        .into_group_map_by(|s| s.execution_thread)
+        .into_iter()
+        .sorted_by_key(|(thread, _)| *thread)
+    {
+        let tracing_time = thread_spans.len() as u128 * overhead.enter_exit;
+        println!(
+            "thread {}: {} enter/exit pairs, tracing overhead: {}ns ({}% of main task)",
+            thread,
+            thread_spans.len(),
+            tracing_time,
+            (tracing_time as f64 / main_duration as f64) * 100.0,
         );
     }
     r
@@ -1,6 +1,8 @@
 //! This module defines an small atomic linked list.
 //! It is safe as long as pushes are serialized which
 //! is the case for our use since only one thread pushes.
+//! Alternatively `push_front_concurrently` can be used from several threads
+//! at once (but not mixed with `push_front`).
 use std::ptr::null_mut;
 use std::sync::atomic::{AtomicPtr, Ordering};
 
@@ -20,13 +22,9 @@ impl<T: 'static> AtomicLinkedList<T> {
             head: AtomicPtr::new(null_mut()),
         }
     }
+    /// Remove (and free) all elements.
     pub(super) fn reset(&self) {
-        let mut node_pointer = self.head.swap(null_mut(), Ordering::SeqCst);
-        while let Some(node) = unsafe { node_pointer.as_ref() } {
-            let old_node_pointer = node_pointer;
-            node_pointer = node.next.load(Ordering::SeqCst);
-            unsafe { old_node_pointer.drop_in_place() }
-        }
+        free_nodes(self.head.swap(null_mut(), Ordering::SeqCst))
     }
     pub(super) fn push_front(&self, elt: T) {
         let new_node = Box::new(Node {
@@ -35,6 +33,59 @@ impl<T: 'static> AtomicLinkedList<T> {
         });
         self.head.store(Box::into_raw(new_node), Ordering::SeqCst)
     }
+    /// Push an element while other threads might be pushing too.
+    pub(super) fn push_front_concurrently(&self, elt: T) {
+        let new_node = Box::into_raw(Box::new(Node {
+            element: elt,
+            next: AtomicPtr::new(self.head.load(Ordering::SeqCst)),
+        }));
+        let new_node_ref = unsafe { &*new_node };
+        let mut head = new_node_ref.next.load(Ordering::SeqCst);
+        while let Err(current_head) =
+            self.head
+                .compare_exchange(head, new_node, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            head = current_head;
+            new_node_ref.next.store(head, Ordering::SeqCst);
+        }
+    }
+    /// Remove (and drop) all elements satisfying given predicate.
+    /// This can run concurrently with `push_front_concurrently` but no other
+    /// removal or iteration should be taking place.
+    pub(super) fn remove_if<F: FnMut(&T) -> bool>(&self, mut predicate: F) {
+        // first, remove from the head, which other threads might change
+        let mut previous = loop {
+            let head = self.head.load(Ordering::SeqCst);
+            match unsafe { head.as_ref() } {
+                None => return,
+                Some(node) if predicate(&node.element) => {
+                    let next = node.next.load(Ordering::SeqCst);
+                    if self
+                        .head
+                        .compare_exchange(head, next, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                    {
+                        drop(unsafe { Box::from_raw(head) })
+                    }
+                }
+                Some(node) => break node,
+            }
+        };
+        // now only us can modify the next pointers
+        loop {
+            let current = previous.next.load(Ordering::SeqCst);
+            match unsafe { current.as_ref() } {
+                None => return,
+                Some(node) if predicate(&node.element) => {
+                    previous
+                        .next
+                        .store(node.next.load(Ordering::SeqCst), Ordering::SeqCst);
+                    drop(unsafe { Box::from_raw(current) })
+                }
+                Some(node) => previous = node,
+            }
+        }
+    }
     pub(super) fn front(&self) -> Option<&T> {
         unsafe { self.head.load(Ordering::Relaxed).as_ref() }.map(|n| &n.element)
     }
@@ -48,6 +99,20 @@ impl<T: 'static> AtomicLinkedList<T> {
     }
 }
 
+impl<T> Drop for AtomicLinkedList<T> {
+    fn drop(&mut self) {
+        free_nodes(*self.head.get_mut())
+    }
+}
+
+/// Drop and free all nodes starting at given one.
+fn free_nodes<T>(mut node_pointer: *mut Node<T>) {
+    while !node_pointer.is_null() {
+        let node = unsafe { Box::from_raw(node_pointer) };
+        node_pointer = node.next.load(Ordering::SeqCst);
+    }
+}
+
 pub(super) struct AtomicLinkedListIterator<T> {
     current_node: *mut Node<T>,
 }
@@ -75,4 +140,15 @@ mod tests {
         list.push_front(3);
         assert!(list.iter().eq(vec![3, 2, 1].iter()))
     }
+    #[test]
+    fn remove_if_test() {
+        let list = AtomicLinkedList::new();
+        for i in 0..6 {
+            list.push_front_concurrently(i);
+        }
+        list.remove_if(|i| i % 2 == 1 || *i == 4);
+        assert!(list.iter().eq([2, 0].iter()));
+        list.remove_if(|_| true);
+        assert!(list.iter().next().is_none());
+    }
 }
@@ -0,0 +1,111 @@
+//! Optional (`log` feature) bridge recording records of the `log` crate
+//! as instant events, attached to the span entered on the logging thread.
+
+/// A log record attached to a span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct LogRecord {
+    /// Time of the record (clock ticks until extraction, then nano seconds).
+    pub(super) time: u128,
+    pub(super) level: &'static str,
+    pub(super) target: String,
+    pub(super) message: String,
+}
+
+/// Color of markers for records of given level.
+pub(super) fn level_color(level: &str) -> &'static str {
+    match level {
+        "ERROR" => "red",
+        "WARN" => "orange",
+        "INFO" => "blue",
+        _ => "grey",
+    }
+}
+
+#[cfg(feature = "log")]
+mod logger {
+    use super::super::clock::now;
+    use super::super::events::log_record;
+    use super::super::subscriber::is_recording_here;
+    use super::LogRecord;
+
+    /// A `log::Log` implementation recording all records as instant events.
+    /// Records are only kept if a recorded span is entered on the logging thread
+    /// and recording is not paused.
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct FastLogger;
+
+    impl log::Log for FastLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &log::Record) {
+            if !is_recording_here() {
+                return;
+            }
+            log_record(LogRecord {
+                time: now() as u128,
+                level: record.level().as_str(),
+                target: record.target().to_owned(),
+                message: record.args().to_string(),
+            })
+        }
+        fn flush(&self) {}
+    }
+
+    static LOGGER: FastLogger = FastLogger;
+
+    /// Record all log records up to given level alongside spans.
+    /// Fails if another logger is already set.
+    pub fn initialize_log_bridge(level: log::LevelFilter) -> Result<(), log::SetLoggerError> {
+        log::set_logger(&LOGGER)?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+#[cfg(feature = "log")]
+pub use self::logger::{initialize_log_bridge, FastLogger};
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use super::super::events::{extract_spans, RECORDING_TESTS};
+    use super::FastLogger;
+    use log::Log;
+
+    fn log(message: &str) {
+        FastLogger.log(
+            &log::Record::builder()
+                .args(format_args!("{}", message))
+                .level(log::Level::Warn)
+                .target("log_test")
+                .build(),
+        )
+    }
+
+    #[test]
+    fn records_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        let dispatch = tracing::Dispatch::new(super::super::FastSubscriber::new());
+        // records are indexed per extraction: the second one starts over
+        for message in ["first", "second"] {
+            tracing::dispatcher::with_default(&dispatch, || {
+                log("outside");
+                tracing::span!(tracing::Level::TRACE, "logging").in_scope(|| {
+                    log(message);
+                    super::super::pause();
+                    log("paused");
+                    super::super::resume();
+                    log("again")
+                })
+            });
+            let spans = extract_spans();
+            let span = spans.values().find(|s| s.name == "logging").unwrap();
+            let messages = span.logs.iter().map(|r| r.message.as_str());
+            assert!(messages.eq([message, "again"]));
+            assert!(span.logs.iter().all(|r| r.level == "WARN"
+                && r.target == "log_test"
+                && span.start <= r.time
+                && r.time <= span.end));
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Export recorded spans as OpenTelemetry OTLP json
+//! (one `resourceSpans` entry holding all spans of a single trace).
+use super::json::json_string;
+use super::{Span, Trace};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// OTLP's `SPAN_KIND_INTERNAL`.
+const INTERNAL_SPAN: u32 = 1;
+
+/// Write given spans as OTLP json.
+/// `origin` is the unix time (in nano seconds) of time 0 in the spans.
+pub(super) fn write_otlp<W: Write>(
+    writer: &mut W,
+    spans: &HashMap<u64, Span>,
+    origin: u128,
+) -> std::io::Result<()> {
+    let trace_id = rand::random::<u128>();
+    writeln!(
+        writer,
+        "{{\"resourceSpans\":[{{\"resource\":{{\"attributes\":[{{\"key\":\"service.name\",\"value\":{{\"stringValue\":\"fast-tracer\"}}}}]}},"
+    )?;
+    writeln!(
+        writer,
+        "\"scopeSpans\":[{{\"scope\":{{\"name\":\"fast-tracer\"}},\"spans\":["
+    )?;
+    for (index, span) in spans.values().sorted_by_key(|s| s.id).enumerate() {
+        if index != 0 {
+            writeln!(writer, ",")?;
+        }
+        write!(
+            writer,
+            "{{\"traceId\":\"{:032x}\",\"spanId\":\"{:016x}\",\"parentSpanId\":\"{}\",\"name\":{},\"kind\":{},\"startTimeUnixNano\":\"{}\",\"endTimeUnixNano\":\"{}\",",
+            trace_id,
+            span.id,
+            span.parent.map(|p| format!("{:016x}", p)).unwrap_or_default(),
+            json_string(span.name),
+            INTERNAL_SPAN,
+            origin + span.start,
+            origin + span.end,
+        )?;
+        write!(
+            writer,
+            "\"attributes\":[{{\"key\":\"thread.id\",\"value\":{{\"intValue\":\"{}\"}}}},{{\"key\":\"fast_tracer.creation_thread\",\"value\":{{\"intValue\":\"{}\"}}}},{{\"key\":\"fast_tracer.kind\",\"value\":{{\"stringValue\":\"{}\"}}}}",
+            span.execution_thread,
+            span.creation_thread,
+            span.kind.field_value()
+        )?;
+        // recorded fields are string attributes
+        for (name, value) in &span.fields {
+            write!(
+                writer,
+                ",{{\"key\":{},\"value\":{{\"stringValue\":{}}}}}",
+                json_string(name),
+                json_string(value)
+            )?;
+        }
+        write!(writer, "]}}")?;
+    }
+    writeln!(writer, "]}}]}}]}}")
+}
+
+/// Records the execution of `op` and saves it as an OTLP json file
+/// (which can be imported by an OpenTelemetry collector or jaeger).
+pub fn otlp<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(path: P, op: F) -> std::io::Result<R> {
+    let (r, trace) = Trace::record(op);
+    trace.save_otlp(path)?;
+    Ok(r)
+}
+
+/// Shortcut for `Trace::extract().save_otlp(path)`.
+pub fn dump_otlp<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_otlp(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::RECORDING_TESTS;
+    use super::*;
+    use tracing::{span, Level};
+
+    #[test]
+    fn otlp_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        let ((), trace) = Trace::record(|| {
+            span!(Level::TRACE, "sized", size = 3, label_text = "a \"b\"").in_scope(|| ())
+        });
+        let mut otlp = Vec::new();
+        write_otlp(&mut otlp, &trace.spans, 1000).unwrap();
+        let otlp = String::from_utf8(otlp).unwrap();
+        assert!(otlp.starts_with("{\"resourceSpans\":[{\"resource\""));
+        let main_task = trace
+            .spans
+            .values()
+            .find(|s| s.name == "main_task")
+            .unwrap();
+        let sized = otlp
+            .lines()
+            .find(|l| l.contains("\"name\":\"sized\""))
+            .unwrap();
+        assert!(sized.contains(&format!("\"parentSpanId\":\"{:016x}\"", main_task.id)));
+        assert!(sized.contains("{\"key\":\"size\",\"value\":{\"stringValue\":\"3\"}}"));
+        assert!(
+            sized.contains("{\"key\":\"label_text\",\"value\":{\"stringValue\":\"a \\\"b\\\"\"}}")
+        );
+        // the whole file is valid json
+        #[cfg(feature = "chrome")]
+        {
+            let json: serde_json::Value = serde_json::from_str(&otlp).unwrap();
+            let spans = &json["resourceSpans"][0]["scopeSpans"][0]["spans"];
+            assert_eq!(spans.as_array().unwrap().len(), 2);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Measure (and optionally compensate) the time spent by the tracer itself.
+use super::events::reset_thread_events;
+use super::FastSubscriber;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tracing::{span, Level};
+
+/// How many spans we trace to measure the overhead.
+const CALIBRATION_SPANS: u32 = 10_000;
+
+/// Calibration spans ids start here (span ids fit in 40 bits)
+/// so they never collide with the ids of a live subscriber.
+/// Extraction ignores them.
+pub(super) const CALIBRATION_IDS: u64 = (1 << 40) - 2 * CALIBRATION_SPANS as u64;
+
+/// Overhead measured by the first calibration.
+static OVERHEAD: OnceLock<Overhead> = OnceLock::new();
+
+/// Nano seconds subtracted for each enter or exit during extraction (as f64 bits).
+/// 0 means no compensation.
+static COMPENSATION: AtomicU64 = AtomicU64::new(0);
+
+/// Cost of tracing on the current machine.
+#[derive(Debug, Clone, Copy)]
+pub struct Overhead {
+    /// Time spent creating a span (in nano seconds).
+    pub new_span: u128,
+    /// Time spent entering and exiting a span (in nano seconds).
+    pub enter_exit: u128,
+}
+
+impl Overhead {
+    /// Total cost of a traced span (creation, enter and exit).
+    pub fn per_span(&self) -> u128 {
+        self.new_span + self.enter_exit
+    }
+}
+
+/// Measure the cost of tracing spans through the `FastSubscriber`
+/// (and its storage) on the current machine.
+/// Calibration runs once, on its own thread, and discards its events
+/// so it can be called at any time, even while recording.
+/// Later calls return the same measure.
+pub fn calibrate() -> Overhead {
+    *OVERHEAD.get_or_init(measure_overhead)
+}
+
+fn measure_overhead() -> Overhead {
+    std::thread::spawn(|| {
+        let subscriber = FastSubscriber::with_first_id(CALIBRATION_IDS);
+        let overhead = tracing::subscriber::with_default(subscriber, || {
+            let start = Instant::now();
+            for _ in 0..CALIBRATION_SPANS {
+                span!(Level::TRACE, "calibration");
+            }
+            let new_span = start.elapsed() / CALIBRATION_SPANS;
+            let span = span!(Level::TRACE, "calibration");
+            let start = Instant::now();
+            for _ in 0..CALIBRATION_SPANS {
+                let _enter = span.enter();
+            }
+            let enter_exit = start.elapsed() / CALIBRATION_SPANS;
+            Overhead {
+                new_span: new_span.as_nanos(),
+                enter_exit: enter_exit.as_nanos(),
+            }
+        });
+        reset_thread_events();
+        overhead
+    })
+    .join()
+    .expect("calibration failed")
+}
+
+/// Subtract given overhead from all recorded durations during extraction.
+/// Every enter or exit costs half the `enter_exit` time, so each execution of a span
+/// is shortened by this cost for its own enter and for each enter or exit inside it.
+/// Start times are not changed so that spans of different threads stay aligned.
+/// Use `None` to disable compensation.
+pub fn compensate_overhead(overhead: Option<Overhead>) {
+    let per_event = overhead.map(|o| o.enter_exit as f64 / 2.0).unwrap_or(0.0);
+    COMPENSATION.store(per_event.to_bits(), Ordering::SeqCst)
+}
+
+/// Nano seconds to subtract for each enter or exit event.
+pub(super) fn compensation_per_event() -> f64 {
+    f64::from_bits(COMPENSATION.load(Ordering::SeqCst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{extract_spans, log_event, RawEvent, RECORDING_TESTS};
+    use super::*;
+
+    #[test]
+    fn compensation_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        // 10us per enter or exit: far more than the real cost
+        compensate_overhead(Some(Overhead {
+            new_span: 0,
+            enter_exit: 20_000,
+        }));
+        tracing::subscriber::with_default(FastSubscriber::new(), || {
+            span!(Level::TRACE, "outer").in_scope(|| {
+                for _ in 0..1_000 {
+                    span!(Level::TRACE, "busy").in_scope(|| ());
+                }
+            });
+            let dispatch = tracing::dispatcher::get_default(|d| d.clone());
+            std::thread::spawn(move || {
+                tracing::dispatcher::with_default(&dispatch, || {
+                    span!(Level::TRACE, "early").in_scope(|| ())
+                })
+            })
+            .join()
+            .unwrap();
+            span!(Level::TRACE, "late").in_scope(|| ());
+        });
+        let spans = extract_spans();
+        compensate_overhead(None);
+        let span = |name| spans.values().find(|s| s.name == name).unwrap();
+        // many events on a thread do not shift it back in time
+        assert!(span("late").start >= span("early").end);
+        let outer = span("outer");
+        assert!(spans
+            .values()
+            .filter(|s| s.name == "busy")
+            .all(|s| s.start >= outer.start && s.end <= outer.end));
+    }
+    #[test]
+    fn compensation_amount_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        // 100ns per enter or exit
+        compensate_overhead(Some(Overhead {
+            new_span: 0,
+            enter_exit: 200,
+        }));
+        // an outer span with 10 children executing one after the other,
+        // then a reference span starting exactly when the outer span ends
+        let children_start = |child: u64| 1_000 + 100_000 * child;
+        let (outer, reference) = (1, 12);
+        for id in 1..13 {
+            log_event(RawEvent::new_span(id, "compensated", 0))
+        }
+        log_event(RawEvent::enter(outer, 0));
+        for child in 0..10 {
+            log_event(RawEvent::enter(child + 2, children_start(child)));
+            log_event(RawEvent::exit(child + 2, children_start(child + 1)));
+        }
+        log_event(RawEvent::exit(outer, 2_000_000));
+        log_event(RawEvent::enter(reference, 2_000_000));
+        log_event(RawEvent::exit(reference, 2_000_001));
+        let spans = extract_spans();
+        compensate_overhead(None);
+        // each child only pays for its own enter
+        for id in 2..11 {
+            let raw_busy_time = spans[&(id + 1)].start - spans[&id].start;
+            assert_eq!(spans[&id].busy_time(), raw_busy_time - 100);
+        }
+        // the outer span also pays for the enter and exit of each child
+        let raw_busy_time = spans[&reference].start - spans[&outer].start;
+        assert_eq!(spans[&outer].busy_time(), raw_busy_time - 21 * 100);
+    }
+}
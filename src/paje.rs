@@ -0,0 +1,228 @@
+//! Export recorded spans as a Paje trace (readable by ViTE and related tools).
+//! Each thread is a container, each span a state and each stolen task
+//! a link from its creation thread to its execution thread.
+use super::spans::thread_states;
+use super::{Span, Trace};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::io::Write;
+
+const HEADER: &str = "%EventDef PajeDefineContainerType 0
+%       Alias string
+%       Type string
+%       Name string
+%EndEventDef
+%EventDef PajeDefineStateType 1
+%       Alias string
+%       Type string
+%       Name string
+%EndEventDef
+%EventDef PajeDefineLinkType 2
+%       Alias string
+%       Type string
+%       StartContainerType string
+%       EndContainerType string
+%       Name string
+%EndEventDef
+%EventDef PajeDefineEntityValue 3
+%       Alias string
+%       Type string
+%       Name string
+%       Color color
+%EndEventDef
+%EventDef PajeCreateContainer 4
+%       Time date
+%       Alias string
+%       Type string
+%       Container string
+%       Name string
+%EndEventDef
+%EventDef PajeDestroyContainer 5
+%       Time date
+%       Type string
+%       Name string
+%EndEventDef
+%EventDef PajePushState 6
+%       Time date
+%       Container string
+%       Type string
+%       Value string
+%EndEventDef
+%EventDef PajePopState 7
+%       Time date
+%       Container string
+%       Type string
+%EndEventDef
+%EventDef PajeStartLink 8
+%       Time date
+%       Container string
+%       Type string
+%       StartContainer string
+%       Value string
+%       Key string
+%EndEventDef
+%EventDef PajeEndLink 9
+%       Time date
+%       Container string
+%       Type string
+%       EndContainer string
+%       Value string
+%       Key string
+%EndEventDef
+0 CT_Prog 0 \"Program\"
+0 CT_Thread CT_Prog \"Thread\"
+1 ST_Task CT_Thread \"Task\"
+2 LT_Steal CT_Prog CT_Thread CT_Thread \"Steal\"
+3 V_Steal LT_Steal \"steal\" \"0.0 0.0 0.0\"";
+
+/// Paje colors (red green blue between 0 and 1), matching the svg colors.
+const PAJE_COLORS: [&str; 7] = [
+    "1.0 0.0 0.0",
+    "0.0 0.0 1.0",
+    "0.0 0.5 0.0",
+    "1.0 1.0 0.0",
+    "0.5 0.0 0.5",
+    "0.65 0.16 0.16",
+    "1.0 0.65 0.0",
+];
+
+/// Paje dates are in seconds.
+fn date(nano: u128) -> String {
+    format!("{}.{:09}", nano / 1_000_000_000, nano % 1_000_000_000)
+}
+
+pub(super) fn write_paje<W: Write>(
+    writer: &mut W,
+    spans: &HashMap<u64, Span>,
+) -> std::io::Result<()> {
+    writeln!(writer, "{}", HEADER)?;
+    let names: HashMap<&'static str, usize> = spans
+        .values()
+        .map(|s| s.name)
+        .unique()
+        .sorted()
+        .enumerate()
+        .map(|(index, name)| (name, index))
+        .collect();
+    for (name, index) in names.iter().sorted_by_key(|(_, index)| **index) {
+        writeln!(
+            writer,
+            "3 V_{} ST_Task \"{}\" \"{}\"",
+            index,
+            name.replace('"', "'"),
+            PAJE_COLORS[index % PAJE_COLORS.len()]
+        )?;
+    }
+    let end = spans.values().map(|s| s.end).max().unwrap_or(0);
+    let spans_per_thread = spans.values().into_group_map_by(|s| s.execution_thread);
+    let threads = spans
+        .values()
+        .flat_map(|s| vec![s.execution_thread, s.creation_thread])
+        .unique()
+        .sorted()
+        .collect::<Vec<_>>();
+    writeln!(writer, "4 {} C_Prog CT_Prog 0 \"program\"", date(0))?;
+    for thread in &threads {
+        writeln!(
+            writer,
+            "4 {} C_T{} CT_Thread C_Prog \"thread {}\"",
+            date(0),
+            thread,
+            thread
+        )?;
+    }
+    // all timed events, as (time, line)
+    let mut events = Vec::new();
+    for (thread, thread_spans) in &spans_per_thread {
+        for (time, state) in thread_states(thread_spans) {
+            let line = match state {
+                Some(name) => format!("6 {} C_T{} ST_Task V_{}", date(time), thread, names[name]),
+                None => format!("7 {} C_T{} ST_Task", date(time), thread),
+            };
+            events.push((time, line));
+        }
+    }
+    for span in spans.values().filter(|s| s.is_stolen()) {
+        events.push((
+            span.start,
+            format!(
+                "8 {} C_Prog LT_Steal C_T{} V_Steal {}",
+                date(span.start),
+                span.creation_thread,
+                span.id
+            ),
+        ));
+        events.push((
+            span.start,
+            format!(
+                "9 {} C_Prog LT_Steal C_T{} V_Steal {}",
+                date(span.start),
+                span.execution_thread,
+                span.id
+            ),
+        ));
+    }
+    // stable sort keeps each thread's states nested
+    events.sort_by_key(|(time, _)| *time);
+    for (_, line) in events {
+        writeln!(writer, "{}", line)?;
+    }
+    for thread in &threads {
+        writeln!(writer, "5 {} CT_Thread C_T{}", date(end), thread)?;
+    }
+    writeln!(writer, "5 {} CT_Prog C_Prog", date(end))
+}
+
+/// Records the execution of `op` and saves it as a Paje trace file
+/// (to be opened with ViTE for example).
+pub fn paje<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(path: P, op: F) -> std::io::Result<R> {
+    let (r, trace) = Trace::record(op);
+    trace.save_paje(path)?;
+    Ok(r)
+}
+
+/// Shortcut for `Trace::extract().save_paje(path)`.
+pub fn dump_paje<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_paje(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn paje_test() {
+        let mut root = Span::new(1);
+        root.name = "root";
+        root.end = 100;
+        let mut child = Span::new(2);
+        child.name = "child";
+        child.parent = Some(1);
+        child.start = 10;
+        child.end = 40;
+        child.execution_thread = 1;
+        let spans = vec![(1, root), (2, child)].into_iter().collect();
+        let mut paje = Vec::new();
+        write_paje(&mut paje, &spans).unwrap();
+        let paje = String::from_utf8(paje).unwrap();
+        let body = paje.strip_prefix(HEADER).unwrap();
+        assert_eq!(
+            body,
+            "
+3 V_0 ST_Task \"child\" \"1.0 0.0 0.0\"
+3 V_1 ST_Task \"root\" \"0.0 0.0 1.0\"
+4 0.000000000 C_Prog CT_Prog 0 \"program\"
+4 0.000000000 C_T0 CT_Thread C_Prog \"thread 0\"
+4 0.000000000 C_T1 CT_Thread C_Prog \"thread 1\"
+6 0.000000000 C_T0 ST_Task V_1
+6 0.000000010 C_T1 ST_Task V_0
+8 0.000000010 C_Prog LT_Steal C_T0 V_Steal 2
+9 0.000000010 C_Prog LT_Steal C_T1 V_Steal 2
+7 0.000000040 C_T1 ST_Task
+7 0.000000100 C_T0 ST_Task
+5 0.000000100 CT_Thread C_T0
+5 0.000000100 CT_Thread C_T1
+5 0.000000100 CT_Prog C_Prog
+"
+        );
+    }
+}
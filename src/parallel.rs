@@ -0,0 +1,317 @@
+//! Instrumented rayon constructs (`rayon` feature), working with stock rayon.
+//!
+//! They produce the same structure as rayon's tracing branch:
+//! fork spans (named `parallel`) whose children execute in parallel
+//! and sequential tasks spans for the work done between forks.
+//! Like rayon's workers, tasks record spans with the global subscriber.
+//! Task spans are created by the thread forking them and entered by the thread
+//! executing them so that stolen tasks can be told apart.
+use rayon::iter::plumbing::{Consumer, Folder, ProducerCallback, UnindexedConsumer};
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use std::cell::{Cell, RefCell};
+use tracing::span::EnteredSpan;
+use tracing::{span, Id, Level, Span};
+
+/// A fork span, child of given parent, entered and exited right away.
+fn fork(parent: Option<u64>) -> Option<u64> {
+    let parent = parent.map(Id::from_u64);
+    let span = span!(parent: parent, Level::TRACE, "parallel", fast_tracer.kind = "fork");
+    span.in_scope(|| ());
+    span.id().map(|id| id.into_u64())
+}
+
+/// A task span, child of given parent, labeled with given label.
+fn task(parent: Option<Id>, label: &'static str) -> Span {
+    span!(parent: parent, Level::TRACE, "task", label = label)
+}
+
+/// Like `rayon::join` but records a fork span with a task span for each closure.
+pub fn join<A, B, RA, RB>(oper_a: A, oper_b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let span = span!(Level::TRACE, "parallel", fast_tracer.kind = "join");
+    let _enter = span.enter();
+    let task_a = task(span.id(), "join_a");
+    let task_b = task(span.id(), "join_b");
+    rayon::join(
+        move || task_a.in_scope(oper_a),
+        move || task_b.in_scope(oper_b),
+    )
+}
+
+/// A rayon scope recording a task span for each spawned closure (see `scope`).
+pub struct Scope<'a, 'scope> {
+    scope: &'a rayon::Scope<'scope>,
+    parent: Option<Id>,
+}
+
+impl<'a, 'scope> Scope<'a, 'scope> {
+    /// Like `rayon::Scope::spawn` but the closure executes inside a task span
+    /// child of the scope's span.
+    pub fn spawn<BODY>(&self, body: BODY)
+    where
+        BODY: for<'b> FnOnce(&Scope<'b, 'scope>) + Send + 'scope,
+    {
+        let parent = self.parent.clone();
+        let span = task(parent.clone(), "spawn");
+        self.scope
+            .spawn(move |scope| span.in_scope(|| body(&Scope { scope, parent })))
+    }
+}
+
+/// Like `rayon::scope` but records a fork span, parent of all spawned tasks.
+pub fn scope<'scope, OP, R>(op: OP) -> R
+where
+    OP: for<'a> FnOnce(&Scope<'a, 'scope>) -> R + Send,
+    R: Send,
+{
+    let span = span!(Level::TRACE, "parallel", fast_tracer.kind = "fork");
+    let parent = span.id();
+    let _enter = span.enter();
+    rayon::scope(move |scope| op(&Scope { scope, parent }))
+}
+
+/// Parallel iterators recording their execution (see `traced`).
+pub trait TracedParallelIterator: ParallelIterator {
+    /// Record the execution of this iterator: a `label` span, parent of a fork span
+    /// for each division of the work and of a task span (labeled `label`)
+    /// for each sequential piece of work.
+    /// Indexed iterators stay indexed but adaptors using their producers
+    /// (like `zip` or `enumerate`) are not recorded.
+    fn traced(self, label: &'static str) -> Traced<Self> {
+        Traced { base: self, label }
+    }
+}
+
+impl<I: ParallelIterator> TracedParallelIterator for I {}
+
+/// A parallel iterator recording its execution (see `TracedParallelIterator::traced`).
+#[derive(Debug, Clone)]
+pub struct Traced<I> {
+    base: I,
+    label: &'static str,
+}
+
+impl<I> Traced<I> {
+    /// Drive the base iterator with given consumer, inside the iterator's span.
+    fn drive_traced<C, R, D: FnOnce(I, TracedConsumer<C>) -> R>(self, consumer: C, drive: D) -> R {
+        let span = span!(
+            Level::TRACE,
+            "traced",
+            label = self.label,
+            fast_tracer.kind = "join"
+        );
+        let _enter = span.enter();
+        let parent = span.id().map(|id| id.into_u64());
+        drive(self.base, TracedConsumer::new(consumer, parent, self.label))
+    }
+}
+
+impl<I: ParallelIterator> ParallelIterator for Traced<I> {
+    type Item = I::Item;
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.drive_traced(consumer, |base, consumer| base.drive_unindexed(consumer))
+    }
+    fn opt_len(&self) -> Option<usize> {
+        self.base.opt_len()
+    }
+}
+
+impl<I: IndexedParallelIterator> IndexedParallelIterator for Traced<I> {
+    fn len(&self) -> usize {
+        self.base.len()
+    }
+    fn drive<C: Consumer<Self::Item>>(self, consumer: C) -> C::Result {
+        self.drive_traced(consumer, |base, consumer| base.drive(consumer))
+    }
+    fn with_producer<CB: ProducerCallback<Self::Item>>(self, callback: CB) -> CB::Output {
+        self.base.with_producer(callback)
+    }
+}
+
+struct TracedConsumer<C> {
+    base: C,
+    /// Id of the current fork span.
+    /// It changes when splitting the consumer (which only borrows us).
+    parent: Cell<Option<u64>>,
+    label: &'static str,
+    /// Task span for our sequential work, created when the consumer is
+    /// and replaced at each split (unentered spans are not recorded).
+    task: RefCell<Span>,
+}
+
+impl<C> TracedConsumer<C> {
+    fn new(base: C, parent: Option<u64>, label: &'static str) -> Self {
+        TracedConsumer {
+            base,
+            parent: Cell::new(parent),
+            label,
+            task: RefCell::new(task(parent.map(Id::from_u64), label)),
+        }
+    }
+}
+
+impl<T, C: Consumer<T>> Consumer<T> for TracedConsumer<C> {
+    type Folder = TracedFolder<C::Folder>;
+    type Reducer = C::Reducer;
+    type Result = C::Result;
+    fn split_at(self, index: usize) -> (Self, Self, Self::Reducer) {
+        let parent = fork(self.parent.get());
+        let label = self.label;
+        let (left, right, reducer) = self.base.split_at(index);
+        (
+            TracedConsumer::new(left, parent, label),
+            TracedConsumer::new(right, parent, label),
+            reducer,
+        )
+    }
+    fn into_folder(self) -> Self::Folder {
+        TracedFolder {
+            base: self.base.into_folder(),
+            span: self.task.into_inner().entered(),
+        }
+    }
+    fn full(&self) -> bool {
+        self.base.full()
+    }
+}
+
+impl<T, C: UnindexedConsumer<T>> UnindexedConsumer<T> for TracedConsumer<C> {
+    fn split_off_left(&self) -> Self {
+        let parent = fork(self.parent.get());
+        self.parent.set(parent);
+        *self.task.borrow_mut() = task(parent.map(Id::from_u64), self.label);
+        TracedConsumer::new(self.base.split_off_left(), parent, self.label)
+    }
+    fn to_reducer(&self) -> Self::Reducer {
+        self.base.to_reducer()
+    }
+}
+
+/// A folder executing inside a task span.
+struct TracedFolder<F> {
+    base: F,
+    span: EnteredSpan,
+}
+
+impl<T, F: Folder<T>> Folder<T> for TracedFolder<F> {
+    type Result = F::Result;
+    fn consume(self, item: T) -> Self {
+        TracedFolder {
+            base: self.base.consume(item),
+            span: self.span,
+        }
+    }
+    fn consume_iter<I>(self, iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        TracedFolder {
+            base: self.base.consume_iter(iter),
+            span: self.span,
+        }
+    }
+    fn complete(self) -> Self::Result {
+        let result = self.base.complete();
+        drop(self.span);
+        result
+    }
+    fn full(&self) -> bool {
+        self.base.full()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{extract_spans, RECORDING_TESTS};
+    use super::super::{FastSubscriber, Span};
+    use super::*;
+    use rayon::iter::IntoParallelIterator;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Is given span a descendant of a span with given name.
+    fn descends_from(span: &Span, name: &str, spans: &HashMap<u64, Span>) -> bool {
+        span.parent
+            .map(|parent| &spans[&parent])
+            .is_some_and(|parent| parent.name == name || descends_from(parent, name, spans))
+    }
+
+    #[test]
+    fn traced_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        // rayon's workers use the global subscriber
+        tracing::subscriber::set_global_default(FastSubscriber::new()).ok();
+        let sum: u64 = (0..10_000u64).into_par_iter().traced("sum").sum();
+        assert_eq!(sum, 49_995_000);
+        let (a, b) = join(|| 1, || scope(|s| s.spawn(|_| ())));
+        assert_eq!((a, b), (1, ()));
+        let spans = extract_spans();
+        let sum_tasks = spans
+            .values()
+            .filter(|s| s.name == "sum")
+            .collect::<Vec<_>>();
+        // the root and at least one task
+        assert!(sum_tasks.len() >= 2);
+        assert!(sum_tasks
+            .iter()
+            .filter(|s| s.parent.is_some())
+            .all(|s| descends_from(s, "sum", &spans)));
+        assert!(spans
+            .values()
+            .filter(|s| s.name == "spawn")
+            .all(|s| descends_from(s, "join_b", &spans)));
+    }
+    #[test]
+    fn steals_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        tracing::subscriber::set_global_default(FastSubscriber::new()).ok();
+        drop(extract_spans());
+        // the first task waits for the second one, which has to be stolen
+        let started = AtomicBool::new(false);
+        let wait = || {
+            while !started.load(Ordering::SeqCst) {
+                std::thread::yield_now()
+            }
+        };
+        let start = || started.store(true, Ordering::SeqCst);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        pool.install(|| join(wait, start));
+        started.store(false, Ordering::SeqCst);
+        pool.install(|| {
+            (0..2)
+                .into_par_iter()
+                .with_max_len(1)
+                .traced("pair")
+                .for_each(|i| if i == 0 { wait() } else { start() })
+        });
+        let mut collected = Vec::new();
+        (0..100u32)
+            .into_par_iter()
+            .traced("indexed")
+            .collect_into_vec(&mut collected);
+        assert!(collected.into_iter().eq(0..100));
+        let spans = extract_spans();
+        let stolen = |name| {
+            spans
+                .values()
+                .filter(|s| s.name == name && s.is_stolen())
+                .count()
+        };
+        assert_eq!(stolen("join_a"), 0);
+        assert_eq!(stolen("join_b"), 1);
+        // the right half of the split
+        assert_eq!(stolen("pair"), 1);
+        assert!(spans.values().filter(|s| s.name == "indexed").count() >= 2);
+    }
+}
@@ -0,0 +1,214 @@
+//! Optional (linux only, `perf` feature) per-thread perf counters.
+//! Counters are read when entering and exiting spans and each span gets
+//! the differences minus the ones of its children, like allocations:
+//! counts are for the time the span was the current span of its thread.
+//! Counters which cannot be opened (for example in containers or
+//! without hardware counters) are simply not recorded.
+
+/// Number of perf counters.
+pub(super) const PERF_COUNTERS: usize = 5;
+
+/// Names of all perf counters, in recording order.
+pub(super) const PERF_COUNTER_NAMES: [&str; PERF_COUNTERS] = [
+    "task-clock",
+    "page-faults",
+    "cpu-migrations",
+    "cycles",
+    "instructions",
+];
+
+/// Differences of perf counters between enter and exit (`None` if unavailable).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct PerfCounters {
+    pub(super) values: [Option<u64>; PERF_COUNTERS],
+}
+
+impl PerfCounters {
+    /// Differences between counters read at enter and at exit.
+    pub(super) fn between(
+        enter: &[Option<u64>; PERF_COUNTERS],
+        exit: &[Option<u64>; PERF_COUNTERS],
+    ) -> Self {
+        let mut counters = PerfCounters::default();
+        for ((value, enter), exit) in counters.values.iter_mut().zip(enter).zip(exit) {
+            if let (Some(enter), Some(exit)) = (enter, exit) {
+                *value = Some(exit.saturating_sub(*enter))
+            }
+        }
+        counters
+    }
+    /// Add given counters minus the ones of the children.
+    pub(super) fn add_exclusive(&mut self, counters: &Self, children: &Self) {
+        for ((value, counter), child) in self
+            .values
+            .iter_mut()
+            .zip(&counters.values)
+            .zip(&children.values)
+        {
+            if let Some(counter) = counter {
+                *value = Some(value.unwrap_or(0) + counter.saturating_sub(child.unwrap_or(0)))
+            }
+        }
+    }
+    /// Add given counters (children counters of a span).
+    pub(super) fn add(&mut self, counters: &Self) {
+        self.add_exclusive(counters, &PerfCounters::default())
+    }
+    /// Names and values of all available counters.
+    pub(super) fn available(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        PERF_COUNTER_NAMES
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| value.map(|value| (*name, value)))
+    }
+}
+
+#[cfg(feature = "perf")]
+mod recording {
+    use super::PERF_COUNTERS;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Do we record perf counters.
+    static RECORDING: AtomicBool = AtomicBool::new(false);
+
+    /// Record (or not) perf counters on each span enter and exit.
+    /// This is only available on linux: returns false (and does nothing) elsewhere.
+    /// Counters which cannot be opened are not recorded.
+    pub fn record_perf_counters(record: bool) -> bool {
+        if cfg!(target_os = "linux") {
+            RECORDING.store(record, Ordering::SeqCst);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Are we recording perf counters.
+    pub(in super::super) fn is_recording() -> bool {
+        RECORDING.load(Ordering::Relaxed)
+    }
+
+    #[cfg(target_os = "linux")]
+    mod linux {
+        use super::PERF_COUNTERS;
+
+        const PERF_TYPE_HARDWARE: u32 = 0;
+        const PERF_TYPE_SOFTWARE: u32 = 1;
+        const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+        const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+        const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+        const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+        const PERF_COUNT_SW_CPU_MIGRATIONS: u64 = 4;
+        const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+        // bits in the attributes flags
+        const EXCLUDE_KERNEL: u64 = 1 << 5;
+        const EXCLUDE_HV: u64 = 1 << 6;
+
+        /// Type and config of each counter (in `PERF_COUNTER_NAMES` order).
+        const EVENTS: [(u32, u64); PERF_COUNTERS] = [
+            (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK),
+            (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS),
+            (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_MIGRATIONS),
+            (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+            (PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+        ];
+
+        /// First version (64 bytes) of the kernel's `perf_event_attr`.
+        #[repr(C)]
+        #[derive(Default)]
+        struct PerfEventAttr {
+            event_type: u32,
+            size: u32,
+            config: u64,
+            sample_period: u64,
+            sample_type: u64,
+            read_format: u64,
+            flags: u64,
+            wakeup_events: u32,
+            bp_type: u32,
+            config1: u64,
+        }
+
+        /// Open a counter for the calling thread, on any cpu.
+        fn open(event_type: u32, config: u64) -> Option<libc::c_int> {
+            let attr = PerfEventAttr {
+                event_type,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                // counting user space only works with restrictive perf_event_paranoid settings
+                flags: EXCLUDE_KERNEL | EXCLUDE_HV,
+                ..Default::default()
+            };
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0 as libc::pid_t,
+                    -1 as libc::c_int,
+                    -1 as libc::c_int,
+                    PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                None
+            } else {
+                Some(fd as libc::c_int)
+            }
+        }
+
+        /// Current thread's opened counters.
+        struct ThreadCounters([Option<libc::c_int>; PERF_COUNTERS]);
+
+        impl Drop for ThreadCounters {
+            fn drop(&mut self) {
+                for fd in self.0.iter().flatten() {
+                    unsafe { libc::close(*fd) };
+                }
+            }
+        }
+
+        thread_local! {
+            static THREAD_COUNTERS: ThreadCounters = {
+                let mut fds = [None; PERF_COUNTERS];
+                for (fd, (event_type, config)) in fds.iter_mut().zip(EVENTS.iter()) {
+                    *fd = open(*event_type, *config);
+                }
+                ThreadCounters(fds)
+            };
+        }
+
+        /// Current values of the thread's counters.
+        pub(in super::super::super) fn counters() -> [Option<u64>; PERF_COUNTERS] {
+            THREAD_COUNTERS
+                .try_with(|counters| {
+                    let mut values = [None; PERF_COUNTERS];
+                    for (value, fd) in values.iter_mut().zip(counters.0.iter()) {
+                        *value = fd.and_then(|fd| {
+                            let mut count: u64 = 0;
+                            let read = unsafe {
+                                libc::read(fd, &mut count as *mut u64 as *mut libc::c_void, 8)
+                            };
+                            if read == 8 {
+                                Some(count)
+                            } else {
+                                None
+                            }
+                        });
+                    }
+                    values
+                })
+                .unwrap_or([None; PERF_COUNTERS])
+        }
+    }
+    #[cfg(target_os = "linux")]
+    pub(in super::super) use self::linux::counters;
+
+    #[cfg(not(target_os = "linux"))]
+    pub(in super::super) fn counters() -> [Option<u64>; PERF_COUNTERS] {
+        [None; PERF_COUNTERS]
+    }
+}
+#[cfg(feature = "perf")]
+pub use self::recording::record_perf_counters;
+#[cfg(feature = "perf")]
+pub(super) use self::recording::{counters, is_recording};
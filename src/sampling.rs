@@ -0,0 +1,234 @@
+//! Sampling of spans of very hot callsites.
+//!
+//! Sampled out spans are not recorded: the subscriber gives them an id with the
+//! `DROPPED` bit set and ignores all their events (see the subscriber).
+//! Their children (explicit or contextual) are dropped as well so that
+//! recorded spans always have a recorded parent.
+//! Recorded spans of sampled names get a `SAMPLING_RATE_FIELD` field so that
+//! they can be extrapolated even after the sampling changed.
+//!
+//! Spans are sampled by name, whatever their callsite. Each thread caches the
+//! rule of each callsite it already saw so that the rules table (behind a lock)
+//! is only accessed again after `set_sampling` or `clear_sampling`.
+//! Rules are never freed: each `set_sampling` costs a few bytes until the end of the program.
+use super::Span;
+use lazy_static::lazy_static;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::RwLock;
+use tracing::Metadata;
+
+/// Bit set in the ids of spans which are not recorded.
+/// Recorded ids fit in 40 bits so this never conflicts.
+pub(super) const DROPPED: u64 = 1 << 63;
+
+/// Name of the field giving the fraction of spans of its name recorded with a span.
+pub(super) const SAMPLING_RATE_FIELD: &str = "fast_tracer.sampling_rate";
+
+/// Size of each thread's cache of callsites rules (a power of two).
+const CALLSITES_CACHE_SIZE: usize = 256;
+
+/// Which spans of a given name to record (see `set_sampling`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// Record one span out of n (the first one, then the n+1-th...).
+    Every(u64),
+    /// Record each span with given probability (in `]0, 1]`).
+    Probability(f64),
+}
+
+impl Sampling {
+    /// Fraction of spans recorded.
+    fn rate(self) -> f64 {
+        match self {
+            Sampling::Every(n) => 1.0 / n.max(1) as f64,
+            Sampling::Probability(p) => p,
+        }
+    }
+}
+
+/// Sampling of a name with its number of spans created so far.
+#[derive(Debug)]
+struct Rule {
+    sampling: Sampling,
+    created: AtomicU64,
+    /// Sampling rate, as recorded in spans.
+    rate: String,
+}
+
+/// What to do with a new span.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Decision {
+    /// Record it: its name is not sampled.
+    Record,
+    /// Record it with given sampling rate (as a field value).
+    RecordSampled(&'static str),
+    /// Do not record it.
+    Drop,
+}
+
+lazy_static! {
+    /// Rule of each sampled name.
+    static ref SAMPLING_BY_NAME: RwLock<HashMap<&'static str, &'static Rule>> =
+        RwLock::new(HashMap::new());
+}
+/// Avoid looking at rules when nothing is sampled.
+static SAMPLING: AtomicBool = AtomicBool::new(false);
+/// Incremented each time the rules change, invalidating the threads caches.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// An empty slot of the callsites cache.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Cell<(usize, u64, Option<&Rule>)> = Cell::new((0, 0, None));
+
+thread_local! {
+    /// Address of the metadata of callsites, generation of the rules and rule, indexed by address.
+    static CALLSITES: [Cell<(usize, u64, Option<&'static Rule>)>; CALLSITES_CACHE_SIZE] =
+        const { [EMPTY_SLOT; CALLSITES_CACHE_SIZE] };
+}
+
+/// Only record some of the spans named `name` (and their descendants),
+/// whatever their callsite.
+/// `stats` extrapolates counts and times of sampled spans.
+///
+/// ```
+/// use fast_tracer::{set_sampling, Sampling};
+/// set_sampling("hot_loop_body", Sampling::Every(100));
+/// set_sampling("cache_lookup", Sampling::Probability(0.05));
+/// ```
+///
+/// # Panics
+///
+/// If the probability is not in `]0, 1]`.
+pub fn set_sampling(name: &'static str, sampling: Sampling) {
+    if let Sampling::Probability(p) = sampling {
+        assert!(
+            p > 0.0 && p <= 1.0,
+            "sampling probability must be in ]0, 1], not {}",
+            p
+        );
+    }
+    let rule = Box::leak(Box::new(Rule {
+        sampling,
+        created: AtomicU64::new(0),
+        rate: sampling.rate().to_string(),
+    }));
+    SAMPLING_BY_NAME.write().unwrap().insert(name, rule);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    SAMPLING.store(true, Ordering::Relaxed);
+}
+
+/// Record all spans again.
+pub fn clear_sampling() {
+    SAMPLING.store(false, Ordering::Relaxed);
+    SAMPLING_BY_NAME.write().unwrap().clear();
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Rule of given callsite, from the thread's cache if the rules did not change.
+fn rule(metadata: &'static Metadata<'static>) -> Option<&'static Rule> {
+    let address = metadata as *const Metadata as usize;
+    let slot = (address >> 4) & (CALLSITES_CACHE_SIZE - 1);
+    let generation = GENERATION.load(Ordering::SeqCst);
+    CALLSITES.with(|cache| {
+        let (cached_address, cached_generation, rule) = cache[slot].get();
+        if (cached_address, cached_generation) == (address, generation) {
+            return rule;
+        }
+        let rule = SAMPLING_BY_NAME
+            .read()
+            .unwrap()
+            .get(metadata.name())
+            .copied();
+        cache[slot].set((address, generation, rule));
+        rule
+    })
+}
+
+/// Should we record a new span of given callsite.
+pub(super) fn sample(metadata: &'static Metadata<'static>) -> Decision {
+    if !SAMPLING.load(Ordering::Relaxed) {
+        return Decision::Record;
+    }
+    let rule = match rule(metadata) {
+        None => return Decision::Record,
+        Some(rule) => rule,
+    };
+    let sampled = match rule.sampling {
+        Sampling::Every(n) => rule.created.fetch_add(1, Ordering::Relaxed) % n.max(1) == 0,
+        Sampling::Probability(p) => rand::random::<f64>() < p,
+    };
+    if sampled {
+        Decision::RecordSampled(&rule.rate)
+    } else {
+        Decision::Drop
+    }
+}
+
+/// Fraction of the spans like given one which were recorded, when it was recorded.
+pub(super) fn sampling_rate(span: &Span) -> f64 {
+    span.fields
+        .iter()
+        .find(|(name, _)| *name == SAMPLING_RATE_FIELD)
+        .and_then(|(_, rate)| rate.parse().ok())
+        .unwrap_or(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{extract_spans, RECORDING_TESTS};
+    use super::super::FastSubscriber;
+    use super::*;
+    use tracing::{span, Level};
+
+    fn record(iterations: usize) {
+        for _ in 0..iterations {
+            let span = span!(Level::TRACE, "sampled");
+            let _enter = span.enter();
+            span!(Level::TRACE, "contextual_child").in_scope(|| ());
+            span!(parent: &span, Level::TRACE, "explicit_child").in_scope(|| ());
+        }
+    }
+
+    #[test]
+    fn sampling_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        drop(extract_spans());
+        set_sampling("sampled", Sampling::Every(4));
+        tracing::subscriber::with_default(FastSubscriber::new(), || {
+            record(8);
+            // callsites caches see the new sampling
+            set_sampling("sampled", Sampling::Every(2));
+            record(4);
+        });
+        clear_sampling();
+        let spans = extract_spans();
+        let count = |name| spans.values().filter(|s| s.name == name).count();
+        assert_eq!(count("sampled"), 4);
+        assert_eq!(count("contextual_child"), 4);
+        assert_eq!(count("explicit_child"), 4);
+        assert!(spans
+            .values()
+            .filter_map(|s| s.parent)
+            .all(|parent| spans[&parent].name == "sampled"));
+        // rates are kept with the spans
+        let mut rates = spans
+            .values()
+            .map(|s| (s.name, sampling_rate(s)))
+            .filter(|(name, _)| *name == "sampled")
+            .map(|(_, rate)| rate)
+            .collect::<Vec<_>>();
+        rates.sort_by(|r1, r2| r1.partial_cmp(r2).unwrap());
+        assert_eq!(rates, [0.25, 0.25, 0.5, 0.5]);
+        assert!(spans
+            .values()
+            .filter(|s| s.name != "sampled")
+            .all(|s| sampling_rate(s) == 1.0));
+    }
+    #[test]
+    #[should_panic(expected = "sampling probability must be in ]0, 1], not 0")]
+    fn probability_test() {
+        set_sampling("never", Sampling::Probability(0.0))
+    }
+}
@@ -0,0 +1,88 @@
+//! Optional recording (on linux) of the core executing each span
+//! and of the context switches happening inside it.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Do we record scheduling information.
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+/// Number of scheduling counters logged on each enter and exit.
+pub(super) const SCHEDULING_COUNTERS: usize = 3;
+
+/// Where a span executed and how often it was switched out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Scheduling {
+    /// Core the span was first entered on.
+    pub(super) start_cpu: u64,
+    /// Core the span was last exited on.
+    pub(super) end_cpu: u64,
+    /// Context switches while the span was entered because the thread blocked.
+    pub(super) voluntary_switches: u64,
+    /// Context switches while the span was entered because the thread was preempted.
+    pub(super) involuntary_switches: u64,
+    /// Did the core change between two samples taken while the span was entered.
+    pub(super) changed_core: bool,
+}
+
+impl Scheduling {
+    /// Did the span move to another core.
+    ///
+    /// The core is only sampled when entering and exiting spans: a span migrated
+    /// if it ended on another core than it started on or if the core changed
+    /// between two samples taken while it was entered (its own enters and exits
+    /// and the ones of its children). Moves back and forth between two samples are missed.
+    pub(super) fn migrated(&self) -> bool {
+        self.changed_core || self.start_cpu != self.end_cpu
+    }
+    /// Update with the counters logged when entering and then exiting the span
+    /// and whether the core changed in between.
+    pub(super) fn add(
+        &mut self,
+        enter: &[u64; SCHEDULING_COUNTERS],
+        exit: &[u64; SCHEDULING_COUNTERS],
+        changed_core: bool,
+    ) {
+        self.end_cpu = exit[0];
+        self.changed_core |= changed_core;
+        self.voluntary_switches += exit[1].saturating_sub(enter[1]);
+        self.involuntary_switches += exit[2].saturating_sub(enter[2]);
+    }
+}
+
+/// Record (or not) on each span enter and exit the current core and the
+/// thread's context switches counts.
+/// This is only available on linux: returns false (and does nothing) elsewhere.
+pub fn record_scheduling(record: bool) -> bool {
+    if cfg!(target_os = "linux") {
+        RECORDING.store(record, Ordering::SeqCst);
+        true
+    } else {
+        false
+    }
+}
+
+/// Are we recording scheduling information.
+pub(super) fn is_recording() -> bool {
+    RECORDING.load(Ordering::Relaxed)
+}
+
+/// Current core, voluntary and involuntary context switches of the current thread.
+#[cfg(target_os = "linux")]
+pub(super) fn counters() -> [u64; SCHEDULING_COUNTERS] {
+    let cpu = unsafe { libc::sched_getcpu() };
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    let usage = if unsafe { libc::getrusage(libc::RUSAGE_THREAD, usage.as_mut_ptr()) } == 0 {
+        unsafe { usage.assume_init() }
+    } else {
+        return [cpu.max(0) as u64, 0, 0];
+    };
+    [
+        cpu.max(0) as u64,
+        usage.ru_nvcsw as u64,
+        usage.ru_nivcsw as u64,
+    ]
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(super) fn counters() -> [u64; SCHEDULING_COUNTERS] {
+    [0; SCHEDULING_COUNTERS]
+}
@@ -0,0 +1,178 @@
+//! Replay the recorded tasks graph on a given number of virtual processors.
+//! We use a greedy list scheduling: whenever a processor is idle and a task is ready
+//! the task is started on the processor.
+use super::graph::Dag;
+use super::svg::{time_string, write_javascript_code, write_task_hover, COLORS};
+use super::{Graph, Node, Task, Trace, SVG_HEIGHT, SVG_WIDTH};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::io::Write;
+
+/// A task placed by the simulated scheduler.
+#[derive(Debug)]
+pub(super) struct ScheduledTask {
+    pub(super) start: u128,
+    pub(super) end: u128,
+    pub(super) processor: usize,
+    pub(super) label: &'static str,
+}
+
+/// Result of a simulated execution.
+#[derive(Debug)]
+pub(super) struct Schedule {
+    pub(super) processors: usize,
+    pub(super) makespan: u128,
+    /// Sum of all tasks durations.
+    pub(super) work: u128,
+    pub(super) tasks: Vec<ScheduledTask>,
+}
+
+impl Schedule {
+    /// Greedily schedule all tasks of given graph on `processors` processors.
+    pub(super) fn new(root: &Node, processors: usize) -> Self {
+        assert!(processors > 0);
+        let dag = Dag::new(root);
+        let mut predecessors_number = dag.predecessors_number.clone();
+        let mut ready: VecDeque<usize> = (0..dag.tasks.len())
+            .filter(|&t| predecessors_number[t] == 0)
+            .collect();
+        let mut idle_processors: Vec<usize> = (0..processors).rev().collect();
+        // running tasks ordered by completion time
+        let mut running = BinaryHeap::new();
+        let mut time = 0;
+        let mut tasks = Vec::with_capacity(dag.tasks.len());
+        loop {
+            while !ready.is_empty() && !idle_processors.is_empty() {
+                let task_index = ready.pop_front().unwrap();
+                let processor = idle_processors.pop().unwrap();
+                let task = dag.tasks[task_index];
+                let end = time + (task.end - task.start);
+                tasks.push(ScheduledTask {
+                    start: time,
+                    end,
+                    processor,
+                    label: task.label,
+                });
+                running.push(Reverse((end, task_index, processor)));
+            }
+            if let Some(Reverse((end, task_index, processor))) = running.pop() {
+                time = end;
+                idle_processors.push(processor);
+                for successor in &dag.successors[task_index] {
+                    predecessors_number[*successor] -= 1;
+                    if predecessors_number[*successor] == 0 {
+                        ready.push_back(*successor);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        Schedule {
+            processors,
+            makespan: time,
+            work: dag.tasks.iter().map(|t| t.end - t.start).sum(),
+            tasks,
+        }
+    }
+
+    fn save_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut svg_file = std::fs::File::create(path)?;
+        writeln!(
+            &mut svg_file,
+            "<svg version='1.1' viewBox='0 0 {} {}' xmlns='http://www.w3.org/2000/svg'>",
+            SVG_WIDTH, SVG_HEIGHT
+        )?;
+        let random_id = rand::random::<u64>();
+        let x_scale = self.makespan.max(1) as f64 / SVG_WIDTH as f64;
+        let height = SVG_HEIGHT as f64 / self.processors as f64;
+        for (task_id, task) in self.tasks.iter().enumerate() {
+            writeln!(
+                &mut svg_file,
+                "<rect class='task{}' id='{}' width='{}' height='{}' x='{}' y='{}' fill='{}'/>",
+                random_id,
+                task_id,
+                (task.end - task.start) as f64 / x_scale,
+                height,
+                task.start as f64 / x_scale,
+                task.processor as f64 * height,
+                COLORS[task.processor % COLORS.len()]
+            )?;
+        }
+        for (task_id, task) in self.tasks.iter().enumerate() {
+            let task = Task {
+                start: task.start,
+                end: task.end,
+                thread: task.processor,
+                label: task.label,
+            };
+            write_task_hover(&mut svg_file, random_id, task_id, &task)?;
+        }
+        write_javascript_code(&mut svg_file, random_id)?;
+        writeln!(&mut svg_file, "</svg>")?;
+        Ok(())
+    }
+}
+
+/// Records the execution of `op` and replays the tasks graph
+/// on `processors` virtual processors.
+/// Prints the predicted makespan and saves the simulated gantt diagram
+/// as an svg file.
+/// This allows to predict the behaviour of the application on more
+/// cores than available.
+/// Fails with `InvalidInput` (without running `op`) if `processors` is 0.
+pub fn simulate_svg<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(
+    path: P,
+    processors: usize,
+    op: F,
+) -> std::io::Result<R> {
+    if processors == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "simulating on 0 processors",
+        ));
+    }
+    let (r, Trace { spans, .. }) = Trace::record(op);
+    let graph = Graph::new(&spans);
+    let schedule = Schedule::new(&graph.root, processors);
+    println!(
+        "measured: {} on {} threads, simulated: {} on {} processors (work: {}, ideal: {})",
+        time_string(graph.end - graph.start),
+        graph.threads_number,
+        time_string(schedule.makespan),
+        processors,
+        time_string(schedule.work),
+        time_string(schedule.work / processors as u128),
+    );
+    schedule.save_svg(path)?;
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn leaf(duration: u128) -> Node {
+        Node::new_from_task(Task {
+            start: 0,
+            end: duration,
+            thread: 0,
+            label: "leaf",
+        })
+    }
+    #[test]
+    fn greedy_schedule_test() {
+        // 1 ; (4 || 2 || 2) ; 1
+        let parallel = Node::new_from_children(vec![leaf(4), leaf(2), leaf(2)].into_iter(), true);
+        let root = Node::new_from_children(vec![leaf(1), parallel, leaf(1)].into_iter(), false);
+        assert_eq!(Schedule::new(&root, 1).makespan, 10);
+        assert_eq!(Schedule::new(&root, 2).makespan, 6);
+        assert_eq!(Schedule::new(&root, 3).makespan, 6);
+    }
+    #[test]
+    fn no_processors_test() {
+        let mut ran = false;
+        let error = simulate_svg("target/never_saved.svg", 0, || ran = true).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(!ran);
+    }
+}
@@ -1,3 +1,90 @@
+use super::allocations::Allocations;
+use super::log_bridge::LogRecord;
+use super::perf::PerfCounters;
+use super::scheduling::Scheduling;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// How a span should be laid out in the tasks graph.
+///
+/// The kind of a span is given by its `fast_tracer.kind` field,
+/// for example `span!(Level::TRACE, "my_join", fast_tracer.kind = "fork")`.
+/// Accepted values are `"task"`, `"fork"` and `"join"`.
+/// When the field is absent the kind is looked up by span name
+/// (see `set_span_kind`) and defaults to `Task`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    /// A sequential task: children execute one after the other,
+    /// interleaved with the span's own work.
+    Task,
+    /// A fork point: children execute in parallel.
+    /// The span itself does no work (like rayon's `parallel` spans).
+    Fork,
+    /// A fork-join region (like rayon's `join`): the span works until
+    /// its first child starts, children execute in parallel and the span
+    /// works again once the last one is done.
+    Join,
+}
+
+impl SpanKind {
+    /// Parse the value of a `fast_tracer.kind` field.
+    pub(super) fn from_field(value: &str) -> Option<Self> {
+        match value {
+            "task" => Some(SpanKind::Task),
+            "fork" => Some(SpanKind::Fork),
+            "join" => Some(SpanKind::Join),
+            _ => None,
+        }
+    }
+    /// Value of the `fast_tracer.kind` field for this kind.
+    pub(super) fn field_value(self) -> &'static str {
+        match self {
+            SpanKind::Task => "task",
+            SpanKind::Fork => "fork",
+            SpanKind::Join => "join",
+        }
+    }
+    /// Are the children of such a span executed in parallel.
+    pub(super) fn is_parallel(self) -> bool {
+        self != SpanKind::Task
+    }
+}
+
+/// Name of the field giving the kind of a span.
+pub(super) const KIND_FIELD: &str = "fast_tracer.kind";
+/// Name of the field giving the name of the thread which created a span.
+pub(super) const THREAD_NAME_FIELD: &str = "thread.name";
+
+lazy_static! {
+    static ref KINDS_BY_NAME: RwLock<HashMap<&'static str, SpanKind>> = RwLock::new(HashMap::new());
+}
+
+/// Lay out all spans named `name` without a `fast_tracer.kind` field as `kind`.
+/// No name is registered by default: users of rayon's tracing branch, which emits
+/// fork points as spans named `"parallel"`, can call
+/// `set_span_kind("parallel", SpanKind::Fork)`.
+pub fn set_span_kind(name: &'static str, kind: SpanKind) {
+    KINDS_BY_NAME.write().unwrap().insert(name, kind);
+}
+
+/// Forget all names registered with `set_span_kind`.
+/// Afterwards only the `fast_tracer.kind` field decides of spans kinds.
+pub fn clear_span_kinds() {
+    KINDS_BY_NAME.write().unwrap().clear();
+}
+
+/// Kind of spans named `name` when they have no `fast_tracer.kind` field.
+pub(super) fn kind_by_name(name: &str) -> SpanKind {
+    KINDS_BY_NAME
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .unwrap_or(SpanKind::Task)
+}
+
 #[derive(Debug)]
 pub(super) struct Span {
     pub(super) id: u64,
@@ -7,6 +94,24 @@ pub(super) struct Span {
     pub(super) name: &'static str,
     pub(super) execution_thread: usize,
     pub(super) creation_thread: usize,
+    pub(super) kind: SpanKind,
+    /// All recorded string fields (except label and kind).
+    pub(super) fields: Vec<(&'static str, &'static str)>,
+    /// Allocations done while this span was the current span of its thread
+    /// (only when using the `TracingAllocator`).
+    pub(super) allocations: Allocations,
+    /// Cores and context switches (only when recording scheduling).
+    pub(super) scheduling: Option<Scheduling>,
+    /// Perf counters while this span was the current span of its thread
+    /// (only when recording them with the `perf` feature).
+    pub(super) perf: PerfCounters,
+    /// Records of the `log` crate logged while this span was the current span
+    /// (only with the `log` feature).
+    pub(super) logs: Vec<LogRecord>,
+    /// Start, end and thread of each execution (between an enter and an exit).
+    /// Futures' spans are entered on each poll so they can have several ones.
+    /// Empty for spans which were not recorded by us (like imported ones).
+    pub(super) intervals: Vec<(u128, u128, usize)>,
 }
 
 impl Span {
@@ -19,6 +124,86 @@ impl Span {
             name: "",
             execution_thread: 0,
             creation_thread: 0,
+            kind: SpanKind::Task,
+            fields: Vec::new(),
+            allocations: Allocations::default(),
+            scheduling: None,
+            perf: PerfCounters::default(),
+            logs: Vec::new(),
+            intervals: Vec::new(),
+        }
+    }
+    /// Time spent executing (not suspended between polls).
+    pub(super) fn busy_time(&self) -> u128 {
+        if self.intervals.is_empty() {
+            self.end - self.start
+        } else {
+            self.intervals
+                .iter()
+                .map(|(start, end, _)| end - start)
+                .sum()
+        }
+    }
+    /// Time spent suspended (between polls).
+    pub(super) fn suspended_time(&self) -> u128 {
+        // a span entered several times at once has overlapping intervals
+        (self.end - self.start).saturating_sub(self.busy_time())
+    }
+    /// Was this span created on a thread and executed on another one.
+    pub(super) fn is_stolen(&self) -> bool {
+        self.creation_thread != self.execution_thread
+    }
+}
+
+/// Push and pop events of all spans executed on one thread,
+/// correctly nested and sorted by time.
+pub(super) fn thread_states(thread_spans: &[&Span]) -> Vec<(u128, Option<&'static str>)> {
+    let mut events = Vec::new();
+    let mut opened: Vec<u128> = Vec::new();
+    for span in thread_spans
+        .iter()
+        .sorted_by_key(|s| (s.start, std::cmp::Reverse(s.end)))
+    {
+        while opened.last().map(|&end| end <= span.start).unwrap_or(false) {
+            events.push((opened.pop().unwrap(), None));
         }
+        events.push((span.start, Some(span.name)));
+        opened.push(span.end);
+    }
+    while let Some(end) = opened.pop() {
+        events.push((end, None));
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn span(id: u64, start: u128, end: u128) -> Span {
+        let mut span = Span::new(id);
+        span.start = start;
+        span.end = end;
+        span.name = "task";
+        span
+    }
+    #[test]
+    fn nested_states_test() {
+        let (outer, inner, next) = (span(1, 0, 10), span(2, 0, 4), span(3, 4, 10));
+        let events = thread_states(&[&next, &inner, &outer]);
+        let times = events
+            .iter()
+            .map(|(t, s)| (*t, s.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                (0, true),
+                (0, true),
+                (4, false),
+                (4, true),
+                (10, false),
+                (10, false)
+            ]
+        );
     }
 }
@@ -0,0 +1,124 @@
+//! Work stealing analysis.
+//! A task is stolen when it is created on one thread and executed on another.
+use super::svg::time_string;
+use super::{Span, Trace};
+use std::collections::{BTreeMap, HashMap};
+
+/// Into how many time slices we cut the execution to compute the steal rate.
+const TIME_SLICES: usize = 10;
+
+#[derive(Debug)]
+pub(super) struct Steals {
+    /// For each span name: (stolen tasks, total tasks).
+    pub(super) per_name: BTreeMap<&'static str, (usize, usize)>,
+    /// Number of tasks stolen for each (creation thread, execution thread) pair.
+    pub(super) per_threads: BTreeMap<(usize, usize), usize>,
+    /// For each time slice: (stolen tasks, total tasks) starting in it.
+    pub(super) over_time: Vec<(usize, usize)>,
+    /// Duration of each time slice.
+    pub(super) slice_duration: u128,
+}
+
+impl Steals {
+    pub(super) fn new(spans: &HashMap<u64, Span>, slices: usize) -> Self {
+        let start = spans.values().map(|s| s.start).min().unwrap_or(0);
+        let end = spans.values().map(|s| s.end).max().unwrap_or(0);
+        let slice_duration = ((end - start) / slices as u128).max(1);
+        let mut steals = Steals {
+            per_name: BTreeMap::new(),
+            per_threads: BTreeMap::new(),
+            over_time: vec![(0, 0); slices],
+            slice_duration,
+        };
+        for span in spans.values() {
+            let stolen = span.is_stolen() as usize;
+            let name_entry = steals.per_name.entry(span.name).or_insert((0, 0));
+            name_entry.0 += stolen;
+            name_entry.1 += 1;
+            let slice = (((span.start - start) / slice_duration) as usize).min(slices - 1);
+            steals.over_time[slice].0 += stolen;
+            steals.over_time[slice].1 += 1;
+            if span.is_stolen() {
+                *steals
+                    .per_threads
+                    .entry((span.creation_thread, span.execution_thread))
+                    .or_insert(0) += 1;
+            }
+        }
+        steals
+    }
+}
+
+/// Records the execution of `op` and prints how many tasks
+/// were stolen (per span name, per threads pair and over time).
+pub fn steals<R, F: FnOnce() -> R>(op: F) -> R {
+    let (r, Trace { spans, .. }) = Trace::record(op);
+    let steals = Steals::new(&spans, TIME_SLICES);
+
+    for (name, (stolen, total)) in &steals.per_name {
+        println!(
+            "{}: {} stolen out of {} ({}%)",
+            name,
+            stolen,
+            total,
+            (*stolen as f64 / *total as f64) * 100.0
+        );
+    }
+    for ((from, to), count) in &steals.per_threads {
+        println!("thread {} -> thread {}: {} steals", from, to, count);
+    }
+    for (slice, (stolen, total)) in steals.over_time.iter().enumerate() {
+        let rate = if *total == 0 {
+            0.0
+        } else {
+            (*stolen as f64 / *total as f64) * 100.0
+        };
+        println!(
+            "[{}, {}]: {}% stolen ({} tasks)",
+            time_string(slice as u128 * steals.slice_duration),
+            time_string((slice + 1) as u128 * steals.slice_duration),
+            rate,
+            total
+        );
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn span(id: u64, name: &'static str, start: u128, threads: (usize, usize)) -> Span {
+        let mut span = Span::new(id);
+        span.name = name;
+        span.start = start;
+        span.end = start + 10;
+        span.creation_thread = threads.0;
+        span.execution_thread = threads.1;
+        span
+    }
+    #[test]
+    fn steals_test() {
+        let spans = vec![
+            span(1, "sort", 0, (0, 0)),
+            span(2, "sort", 20, (0, 1)),
+            span(3, "merge", 45, (0, 1)),
+            span(4, "merge", 60, (1, 2)),
+            span(5, "merge", 90, (1, 1)),
+        ]
+        .into_iter()
+        .map(|s| (s.id, s))
+        .collect::<HashMap<_, _>>();
+        let steals = Steals::new(&spans, 4);
+        assert_eq!(
+            steals.per_name.into_iter().collect::<Vec<_>>(),
+            vec![("merge", (2, 3)), ("sort", (1, 2))]
+        );
+        assert_eq!(
+            steals.per_threads.into_iter().collect::<Vec<_>>(),
+            vec![((0, 1), 2), ((1, 2), 1)]
+        );
+        // 100 time units cut in slices of 25, the last span starts in the last one
+        assert_eq!(steals.slice_duration, 25);
+        assert_eq!(steals.over_time, vec![(1, 2), (1, 1), (1, 1), (0, 1)]);
+    }
+}
@@ -2,7 +2,8 @@
 //! every thread has its own storage and will be the only one to write in it.
 use super::list::AtomicLinkedList;
 
-const BLOCK_SIZE: usize = 10_000;
+/// Size of a block in bytes: small elements get more elements per block.
+const BLOCK_BYTES: usize = 400_000;
 
 /// We store elements in a list of blocks.
 /// Each `Block` is a contiguous memory block.
@@ -18,22 +19,25 @@ impl<T> Default for Block<T> {
 }
 
 impl<T> Block<T> {
+    /// How many elements fit in a block.
+    const CAPACITY: usize = BLOCK_BYTES / std::mem::size_of::<T>();
+
     /// Create a new block.
     fn new() -> Self {
         Block {
-            data: Vec::with_capacity(BLOCK_SIZE),
+            data: Vec::with_capacity(Self::CAPACITY),
         }
     }
 
     /// Add given element to block.
     fn push(&mut self, element: T) {
-        debug_assert!(self.data.len() != BLOCK_SIZE);
+        debug_assert!(self.data.len() != Self::CAPACITY);
         self.data.push(element)
     }
 
     /// Is there some space left.
     fn is_full(&self) -> bool {
-        self.data.len() == BLOCK_SIZE
+        self.data.len() == Self::CAPACITY
     }
 
     /// Iterator on all elements.
@@ -44,6 +48,7 @@ impl<T> Block<T> {
 
 /// Fast structure (worst case O(1)) for pushing
 /// logs in a thread.
+/// Blocks are freed when resetting or dropping the storage.
 #[derive(Debug)]
 pub(crate) struct Storage<T> {
     data: AtomicLinkedList<Block<T>>,
@@ -76,6 +81,11 @@ impl<T: 'static> Storage<T> {
         }
         self.data.front_mut().unwrap().push(element)
     }
+    /// Did we store nothing since last reset.
+    pub(super) fn is_empty(&self) -> bool {
+        // blocks are only added when needed so only the first one can be empty
+        self.data.front().map(|b| b.data.is_empty()).unwrap_or(true)
+    }
     pub(super) fn reset(&self) {
         self.data.reset();
         let first_block = Block::new();
@@ -1,31 +1,97 @@
+use super::allocations;
+use super::clock::{self, now};
+use super::sampling::{self, Decision, DROPPED, SAMPLING_RATE_FIELD};
+use super::scheduling;
 use super::{log_event, RawEvent};
-use lazy_static::lazy_static;
-use std::sync::atomic::AtomicU64;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use tracing::event::Event;
-use tracing::field::{Field, Value, Visit};
+use tracing::field::{Field, Visit};
 use tracing::span::{Attributes, Record};
 use tracing::subscriber::Subscriber;
 use tracing::Id;
 use tracing::Metadata;
 
-lazy_static! {
-    static ref START: std::time::Instant = std::time::Instant::now();
+/// Are we recording. Spans entered while it is false are not recorded (see `pause`).
+/// Storing into it is async-signal-safe: a signal handler can toggle recording.
+pub static RECORDING: AtomicBool = AtomicBool::new(true);
+
+/// Stop recording spans, without removing the subscriber.
+///
+/// Spans created or entered while paused are not recorded, nor are the spans
+/// entered inside them, even after `resume`. Spans entered before pausing are
+/// still exited normally (in any order) so that they stay consistent.
+/// Log records are not recorded either.
+pub fn pause() {
+    RECORDING.store(false, Ordering::Relaxed)
+}
+
+/// Record spans again after a `pause`.
+pub fn resume() {
+    RECORDING.store(true, Ordering::Relaxed)
+}
+
+thread_local! {
+    /// Ids of the spans currently entered on this thread whose enter was not recorded
+    /// (dropped by sampling or entered while paused), in entering order.
+    /// Spans entered inside them are not recorded either.
+    /// Spans may be exited in any order: exits are matched by id.
+    static UNRECORDED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+fn in_unrecorded_span() -> bool {
+    UNRECORDED.with(|unrecorded| !unrecorded.borrow().is_empty())
+}
+
+/// Forget given unrecorded entry of a span, returning false if it was recorded.
+fn exit_unrecorded(span: u64) -> bool {
+    UNRECORDED.with(|unrecorded| {
+        let mut unrecorded = unrecorded.borrow_mut();
+        match unrecorded.iter().rposition(|id| *id == span) {
+            Some(position) => {
+                unrecorded.remove(position);
+                true
+            }
+            None => false,
+        }
+    })
 }
 
-fn now() -> u128 {
-    START.elapsed().as_nanos()
+/// Are events logged now by the current thread recorded
+/// (we are not paused and not inside an unrecorded span).
+#[cfg(feature = "log")]
+pub(super) fn is_recording_here() -> bool {
+    RECORDING.load(Ordering::Relaxed) && !in_unrecorded_span()
 }
 
 pub struct FastSubscriber {
     next_task_id: AtomicU64,
+    /// Do `pause` and `resume` apply to us.
+    pausable: bool,
 }
 
 impl FastSubscriber {
     pub fn new() -> Self {
         FastSubscriber {
-            next_task_id: AtomicU64::new(1),
+            pausable: true,
+            ..FastSubscriber::with_first_id(1)
         }
     }
+    /// A subscriber numbering its spans from `first_id`
+    /// (so that spans of different subscribers do not collide)
+    /// and recording even while paused, for calibration.
+    pub(super) fn with_first_id(first_id: u64) -> Self {
+        // start the clock now rather than at first event
+        clock::initialize();
+        FastSubscriber {
+            next_task_id: AtomicU64::new(first_id),
+            pausable: false,
+        }
+    }
+    /// Are we paused.
+    fn is_paused(&self) -> bool {
+        self.pausable && !RECORDING.load(Ordering::Relaxed)
+    }
 }
 
 impl Subscriber for FastSubscriber {
@@ -38,11 +104,31 @@ impl Subscriber for FastSubscriber {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let parent = span.parent().map(|p| p.into_u64()).unwrap_or(0);
         let name = span.metadata().name();
-        log_event(RawEvent::NewSpan(new_id, name, parent));
+        // children of dropped spans are dropped to keep the recorded tree consistent
+        let dropped_parent = if span.is_contextual() {
+            in_unrecorded_span()
+        } else {
+            parent & DROPPED != 0
+        };
+        let decision = if self.is_paused() || dropped_parent {
+            Decision::Drop
+        } else {
+            sampling::sample(span.metadata())
+        };
+        if decision == Decision::Drop {
+            return Id::from_u64(new_id | DROPPED);
+        }
+        log_event(RawEvent::new_span(new_id, name, parent));
+        if let Decision::RecordSampled(rate) = decision {
+            log_event(RawEvent::str_field(new_id, SAMPLING_RATE_FIELD, rate));
+        }
         span.record(&mut FastVisitor(new_id));
         Id::from_u64(new_id)
     }
     fn record(&self, span: &Id, values: &Record) {
+        if span.into_u64() & DROPPED != 0 {
+            return;
+        }
         values.record(&mut FastVisitor(span.into_u64()))
     }
     fn record_follows_from(&self, _span: &Id, _follows: &Id) {
@@ -52,10 +138,55 @@ impl Subscriber for FastSubscriber {
         unimplemented!()
     }
     fn enter(&self, span: &Id) {
-        log_event(RawEvent::Enter(span.into_u64(), now()))
+        if span.into_u64() & DROPPED != 0 || in_unrecorded_span() || self.is_paused() {
+            return UNRECORDED.with(|unrecorded| unrecorded.borrow_mut().push(span.into_u64()));
+        }
+        log_event(RawEvent::enter(span.into_u64(), now()));
+        log_allocations(span.into_u64(), false);
+        log_scheduling(span.into_u64(), false);
+        #[cfg(feature = "perf")]
+        log_perf_counters(span.into_u64(), false);
     }
     fn exit(&self, span: &Id) {
-        log_event(RawEvent::Exit(span.into_u64(), now()));
+        if exit_unrecorded(span.into_u64()) {
+            return;
+        }
+        #[cfg(feature = "perf")]
+        log_perf_counters(span.into_u64(), true);
+        log_scheduling(span.into_u64(), true);
+        log_allocations(span.into_u64(), true);
+        log_event(RawEvent::exit(span.into_u64(), now()));
+    }
+}
+
+/// Log the allocations counters of the current thread (if we track them).
+fn log_allocations(span: u64, exiting: bool) {
+    if allocations::is_tracking() {
+        let counters = allocations::thread_allocations().counters();
+        for (counter, value) in counters.iter().enumerate() {
+            log_event(RawEvent::allocations(span, exiting, counter as u32, *value));
+        }
+    }
+}
+
+/// Log the current core and context switches counts (if we record them).
+fn log_scheduling(span: u64, exiting: bool) {
+    if scheduling::is_recording() {
+        for (counter, value) in scheduling::counters().iter().enumerate() {
+            log_event(RawEvent::scheduling(span, exiting, counter as u32, *value));
+        }
+    }
+}
+
+/// Log the available perf counters (if we record them).
+#[cfg(feature = "perf")]
+fn log_perf_counters(span: u64, exiting: bool) {
+    if super::perf::is_recording() {
+        for (counter, value) in super::perf::counters().iter().enumerate() {
+            if let Some(value) = value {
+                log_event(RawEvent::perf(span, exiting, counter as u32, *value));
+            }
+        }
     }
 }
 
@@ -69,8 +200,100 @@ struct FastVisitor(u64);
 
 impl Visit for FastVisitor {
     fn record_str(&mut self, field: &Field, value: &str) {
-        let static_str = unsafe { std::mem::transmute::<&str, &'static str>(value) };
-        log_event(RawEvent::StrField(self.0, field.name(), static_str));
+        log_event(RawEvent::str_field(self.0, field.name(), value));
+    }
+    // integers, booleans and other values are recorded as their debug string
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        log_event(RawEvent::str_field(
+            self.0,
+            field.name(),
+            &format!("{:?}", value),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{extract_spans, RECORDING_TESTS};
+    use super::*;
+    use tracing::{span, Level};
+
+    #[test]
+    fn fields_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        tracing::subscriber::with_default(FastSubscriber::new(), || {
+            span!(Level::TRACE, "fields", text = "a", count = 3, flag = true).in_scope(|| ())
+        });
+        let spans = extract_spans();
+        let span = spans.values().next().unwrap();
+        assert_eq!(
+            span.fields,
+            vec![("text", "a"), ("count", "3"), ("flag", "true")]
+        );
+    }
+
+    #[test]
+    fn pause_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        tracing::subscriber::with_default(FastSubscriber::new(), || {
+            let before = span!(Level::TRACE, "before");
+            let entered_before = before.enter();
+            pause();
+            span!(Level::TRACE, "paused").in_scope(|| {
+                // still inside a span entered while paused
+                resume();
+                span!(Level::TRACE, "inside_paused").in_scope(|| ())
+            });
+            let recorded_later = span!(Level::TRACE, "recorded_later");
+            pause();
+            let created_paused = span!(Level::TRACE, "created_paused");
+            recorded_later.in_scope(|| ());
+            drop(entered_before);
+            resume();
+            created_paused.in_scope(|| ());
+            recorded_later.in_scope(|| ());
+            span!(Level::TRACE, "after").in_scope(|| ());
+            // guards are not always dropped in reverse order
+            let recorded = span!(Level::TRACE, "recorded");
+            let entered_recorded = recorded.enter();
+            pause();
+            let unrecorded = span!(Level::TRACE, "unrecorded");
+            let entered_unrecorded = unrecorded.enter();
+            drop(entered_recorded);
+            resume();
+            drop(entered_unrecorded);
+        });
+        // calibration records even while paused
+        pause();
+        tracing::subscriber::with_default(FastSubscriber::with_first_id(1 << 20), || {
+            span!(Level::TRACE, "calibrating").in_scope(|| ())
+        });
+        resume();
+        let spans = extract_spans();
+        let mut names = spans.values().map(|s| s.name).collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec![
+                "after",
+                "before",
+                "calibrating",
+                "recorded",
+                "recorded_later"
+            ]
+        );
+        assert!(spans.values().all(|s| s.intervals.len() == 1));
+        assert!(!in_unrecorded_span());
+    }
+    #[test]
+    fn paused_record_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        pause();
+        let (r, trace) =
+            super::super::Trace::record(|| span!(Level::TRACE, "paused").in_scope(|| 3));
+        let stats = super::super::stats(|| 4);
+        resume();
+        assert_eq!((r, stats), (3, 4));
+        assert!(trace.spans.is_empty());
     }
-    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {}
 }
@@ -1,19 +1,18 @@
 use crate::spans::Span;
 
-use super::FastSubscriber;
-use super::{extract_spans, reset_events, Graph};
+use super::log_bridge::level_color;
+use super::{Graph, Trace};
 use super::{Node, Task};
 use either::Either;
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::Write;
-use tracing::{span, Level};
 
 pub(super) const SVG_WIDTH: u128 = 1920;
 pub(super) const SVG_HEIGHT: u128 = 1080;
 
-const COLORS: [&str; 7] = [
+pub(super) const COLORS: [&str; 7] = [
     "red", "blue", "green", "yellow", "purple", "brown", "orange",
 ];
 
@@ -28,37 +27,33 @@ pub fn display_svg<R, F: FnOnce() -> R>(op: F) -> std::io::Result<R> {
 }
 
 pub fn svg<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(path: P, op: F) -> std::io::Result<R> {
-    let subscriber: FastSubscriber = FastSubscriber::new();
-    tracing::subscriber::set_global_default(subscriber).err();
-    reset_events();
-    let span = span!(Level::TRACE, "main_task");
-    let r = {
-        let _enter = span.enter();
-        op()
-    };
-    let spans = extract_spans();
-    let graph = Graph::new(&spans);
-    graph.save_svg(path)?;
+    let (r, trace) = Trace::record(op);
+    trace.save_svg(path)?;
     Ok(r)
 }
 
+/// Saves an svg displaying the tasks graph of everything recorded
+/// since the last extraction (or since the start of the program).
+/// This is meant to be used with `initialize_logger` when wrapping
+/// the traced code inside a closure is not an option.
+pub fn dump_svg<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_svg(path)
+}
+
+/// Saves an svg displaying the gantt diagram of everything recorded
+/// since the last extraction (or since the start of the program).
+pub fn dump_gantt_svg<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_gantt_svg(path)
+}
+
 /// Saves an svg displaying the gantt diagram
 /// of the recorded execution of `op`.
 pub fn gantt_svg<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(
     path: P,
     op: F,
 ) -> std::io::Result<R> {
-    let subscriber: FastSubscriber = FastSubscriber::new();
-    tracing::subscriber::set_global_default(subscriber).err();
-    reset_events();
-    let span = span!(Level::TRACE, "main_task");
-    let r = {
-        let _enter = span.enter();
-        op()
-    };
-    let spans = extract_spans();
-    let gantt = Gantt::new(&spans);
-    gantt.save_svg(&path)?;
+    let (r, trace) = Trace::record(op);
+    trace.save_gantt_svg(path)?;
     Ok(r)
 }
 
@@ -73,7 +68,7 @@ pub(super) struct Gantt<'a> {
 }
 
 impl<'a> Gantt<'a> {
-    fn new(spans: &'a HashMap<u64, Span>) -> Self {
+    pub(super) fn new(spans: &'a HashMap<u64, Span>) -> Self {
         let mut nb_threads = 0;
         let mut start = u128::MAX;
         let mut end: u128 = 0;
@@ -99,7 +94,7 @@ impl<'a> Gantt<'a> {
         }
     }
 
-    fn save_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+    pub(super) fn save_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
         let mut svg_file = std::fs::File::create(path)?;
         let random_id = rand::random::<u64>();
         writeln!(
@@ -117,6 +112,9 @@ impl<'a> Gantt<'a> {
         for (_, span) in self.spans {
             self.write_task(writer, span, &mut seen, random_id)?;
         }
+        self.write_steals(writer, random_id)?;
+        self.write_scheduling(writer)?;
+        self.write_logs(writer)?;
         for (span_id, span) in self.spans {
             self.write_task_hover(writer, random_id, &span_id, span)?;
         }
@@ -135,6 +133,11 @@ impl<'a> Gantt<'a> {
             if let Some(father) = span.parent {
                 self.write_task(writer, self.spans.get(&father).unwrap(), seen, random_id)?;
             }
+            if span.intervals.len() > 1 {
+                self.write_polls(writer, span, random_id)?;
+                seen.insert(span.id);
+                return Ok(());
+            }
             writeln!(
                 writer,
                 "<rect class='{}' id='{}' width='{}' height='{}' x='{}' y='{}' fill='{}'/>",
@@ -151,6 +154,119 @@ impl<'a> Gantt<'a> {
         Ok(())
     }
 
+    /// Draw each execution of a span entered several times (like a future's span)
+    /// with a dashed line over the suspended time between them.
+    fn write_polls<W: Write>(
+        &self,
+        writer: &mut W,
+        span: &Span,
+        random_id: u64,
+    ) -> std::io::Result<()> {
+        let thread_height = SVG_HEIGHT as f32 / self.nb_threads as f32;
+        let x =
+            |time: u128| ((time - self.start) * SVG_WIDTH) as f32 / (self.end - self.start) as f32;
+        for (start, end, thread) in &span.intervals {
+            writeln!(
+                writer,
+                "<rect class='task{}' id='{}' width='{}' height='{}' x='{}' y='{}' fill='{}'/>",
+                random_id,
+                span.id,
+                x(*end) - x(*start),
+                thread_height,
+                x(*start),
+                thread_height * *thread as f32,
+                COLORS[self.span_colors[span.name]],
+            )?;
+        }
+        for ((_, end, thread), (next_start, _, next_thread)) in
+            span.intervals.iter().tuple_windows()
+        {
+            writeln!(
+                writer,
+                "<line x1='{}' y1='{}' x2='{}' y2='{}' stroke='{}' stroke-width='2' stroke-dasharray='4' pointer-events='none'/>",
+                x(*end),
+                thread_height * (*thread as f32 + 0.5),
+                x(*next_start),
+                thread_height * (*next_thread as f32 + 0.5),
+                COLORS[self.span_colors[span.name]],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Draw an arrow from creation thread to execution thread
+    /// for each stolen task.
+    fn write_steals<W: Write>(&self, writer: &mut W, random_id: u64) -> std::io::Result<()> {
+        let thread_height = SVG_HEIGHT as f32 / self.nb_threads as f32;
+        writeln!(
+            writer,
+            "<defs><marker id='arrow_{}' viewBox='0 0 10 10' refX='10' refY='5' markerWidth='6' markerHeight='6' orient='auto'><path d='M 0 0 L 10 5 L 0 10 z'/></marker></defs>",
+            random_id
+        )?;
+        for span in self.spans.values().filter(|s| s.is_stolen()) {
+            let x = ((span.start - self.start) * SVG_WIDTH) as f32 / (self.end - self.start) as f32;
+            writeln!(
+                writer,
+                "<line x1='{}' y1='{}' x2='{}' y2='{}' stroke='black' stroke-width='2' marker-end='url(#arrow_{})'/>",
+                x,
+                thread_height * (span.creation_thread as f32 + 0.5),
+                x,
+                thread_height * (span.execution_thread as f32 + 0.5),
+                random_id
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Outline spans which were preempted (in red)
+    /// or which moved to another core (dashed).
+    fn write_scheduling<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let thread_height = SVG_HEIGHT as f32 / self.nb_threads as f32;
+        for (span, scheduling) in self
+            .spans
+            .values()
+            .filter_map(|s| s.scheduling.map(|scheduling| (s, scheduling)))
+            .filter(|(_, scheduling)| scheduling.migrated() || scheduling.involuntary_switches > 0)
+        {
+            writeln!(
+                writer,
+                "<rect width='{}' height='{}' x='{}' y='{}' fill='none' stroke='{}' stroke-width='2'{} pointer-events='none'/>",
+                ((span.end - span.start) * SVG_WIDTH) as f32 / (self.end - self.start) as f32,
+                thread_height,
+                ((span.start - self.start) * SVG_WIDTH) as f32 / (self.end - self.start) as f32,
+                thread_height * span.execution_thread as f32,
+                if scheduling.involuntary_switches > 0 { "red" } else { "black" },
+                if scheduling.migrated() { " stroke-dasharray='4'" } else { "" },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Draw a marker for each log record, colored by level.
+    fn write_logs<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let thread_height = SVG_HEIGHT as f32 / self.nb_threads as f32;
+        for span in self.spans.values() {
+            for record in &span.logs {
+                let x = ((record.time - self.start) * SVG_WIDTH) as f32
+                    / (self.end - self.start) as f32;
+                let y = thread_height * span.execution_thread as f32;
+                writeln!(
+                    writer,
+                    "<line x1='{}' y1='{}' x2='{}' y2='{}' stroke='{}' stroke-width='2'><title>{} {}: {}</title></line>",
+                    x,
+                    y,
+                    x,
+                    y + thread_height,
+                    level_color(record.level),
+                    record.level,
+                    xml_escape(&record.target),
+                    xml_escape(&record.message)
+                )?;
+            }
+        }
+        Ok(())
+    }
+
     fn write_task_hover<W: Write>(
         &self,
         writer: &mut W,
@@ -158,13 +274,50 @@ impl<'a> Gantt<'a> {
         span_id: &u64,
         span: &Span,
     ) -> std::io::Result<()> {
-        let label = format!(
+        let mut label = format!(
             "start {} end {}\nduration {}\nlabel {}",
             span.start,
             span.end,
             time_string(span.end - span.start),
             span.name
         );
+        if span.intervals.len() > 1 {
+            label += &format!(
+                "\n{} polls, suspended {}",
+                span.intervals.len(),
+                time_string(span.suspended_time())
+            );
+        }
+        if let Some(scheduling) = span.scheduling {
+            label += &format!(
+                "\ncpu {} to {}{}\n{} preemptions, {} voluntary switches",
+                scheduling.start_cpu,
+                scheduling.end_cpu,
+                if scheduling.changed_core {
+                    " (changed in between)"
+                } else {
+                    ""
+                },
+                scheduling.involuntary_switches,
+                scheduling.voluntary_switches
+            );
+        }
+        for (name, value) in span.perf.available() {
+            label += &format!("\n{} {}", name, value);
+        }
+        for record in &span.logs {
+            label += &format!(
+                "\n{} {}",
+                record.level,
+                xml_escape(&record.message.chars().take(40).collect::<String>())
+            );
+        }
+        if span.allocations != Default::default() {
+            label += &format!(
+                "\n{} allocations, {} bytes\n{} bytes freed",
+                span.allocations.count, span.allocations.allocated, span.allocations.freed
+            );
+        }
         writeln!(writer, "<g id=\"tip_{}_{}\">", random_id, span_id)?;
         let x = SVG_WIDTH - 400;
         let height = label.lines().count() as u32 * 20;
@@ -185,7 +338,7 @@ impl<'a> Gantt<'a> {
 }
 
 impl Graph {
-    fn save_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+    pub(super) fn save_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
         let mut svg_file = std::fs::File::create(path)?;
         writeln!(
             &mut svg_file,
@@ -193,7 +346,7 @@ impl Graph {
             SVG_WIDTH, SVG_HEIGHT
         )?;
         // let's animate for 30 seconds
-        let time_dilation = 30_000.0 / (self.end - self.start) as f64;
+        let time_dilation = 30_000.0 / (self.end - self.start).max(1) as f64;
         let random_id = rand::random(); // so we can include several logs in the same webpage
         self.root
             .write_edges_svg(&mut svg_file, &Vec::new(), &Vec::new())?;
@@ -207,7 +360,10 @@ impl Graph {
     }
 }
 
-fn write_javascript_code<W: Write>(writer: &mut W, random_id: u64) -> std::io::Result<()> {
+pub(super) fn write_javascript_code<W: Write>(
+    writer: &mut W,
+    random_id: u64,
+) -> std::io::Result<()> {
     // this part will allow to get more info on tasks by hovering over them
     writeln!(
         writer,
@@ -249,7 +405,14 @@ fn write_javascript_code<W: Write>(writer: &mut W, random_id: u64) -> std::io::R
     )
 }
 
-fn write_task_hover<W: Write>(
+/// Escape given text to include it in svg.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub(super) fn write_task_hover<W: Write>(
     writer: &mut W,
     random_id: u64,
     task_id: usize,
@@ -514,7 +677,7 @@ fn write_edge_svg<W: Write>(
 }
 
 /// Convert nano seconds to human readable string.
-fn time_string(nano: u128) -> String {
+pub(super) fn time_string(nano: u128) -> String {
     match nano {
         n if n < 1_000 => format!("{}ns", n),
         n if n < 1_000_000 => format!("{:.2}us", time_float(n, 1_000)),
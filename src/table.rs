@@ -0,0 +1,265 @@
+//! Export recorded spans as a flat table (one row per span)
+//! in csv or (with the `arrow` feature) arrow ipc format.
+use super::{Span, Trace};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Columns of the table before the recorded fields.
+pub(super) const FIXED_COLUMNS: [&str; 9] = [
+    "id",
+    "parent",
+    "name",
+    "start",
+    "end",
+    "duration",
+    "execution_thread",
+    "creation_thread",
+    "depth",
+];
+
+/// Flat view of the spans: rows sorted by id, with their depth
+/// and the names of all recorded fields (one column each).
+pub(super) struct Table<'a> {
+    pub(super) rows: Vec<&'a Span>,
+    pub(super) depths: HashMap<u64, usize>,
+    pub(super) field_names: Vec<&'static str>,
+}
+
+impl<'a> Table<'a> {
+    pub(super) fn new(spans: &'a HashMap<u64, Span>) -> Self {
+        let mut depths = HashMap::new();
+        for id in spans.keys() {
+            depth(*id, spans, &mut depths);
+        }
+        Table {
+            rows: spans.values().sorted_by_key(|s| s.id).collect(),
+            depths,
+            field_names: spans
+                .values()
+                .flat_map(|s| s.fields.iter().map(|(name, _)| *name))
+                .unique()
+                .sorted()
+                .collect(),
+        }
+    }
+    /// Column name of given field: fields named like a fixed column are prefixed with `field.`.
+    pub(super) fn column_name(field_name: &str) -> String {
+        if FIXED_COLUMNS.contains(&field_name) {
+            format!("field.{}", field_name)
+        } else {
+            field_name.to_owned()
+        }
+    }
+    /// Value of given field in given span.
+    pub(super) fn field(span: &Span, name: &str) -> Option<&'static str> {
+        span.fields
+            .iter()
+            .find(|(field_name, _)| *field_name == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Number of ancestors of given span (memoized).
+fn depth(id: u64, spans: &HashMap<u64, Span>, depths: &mut HashMap<u64, usize>) -> usize {
+    if let Some(depth) = depths.get(&id) {
+        return *depth;
+    }
+    let depth = match spans.get(&id).and_then(|s| s.parent) {
+        Some(parent) if spans.contains_key(&parent) => depth(parent, spans, depths) + 1,
+        _ => 0,
+    };
+    depths.insert(id, depth);
+    depth
+}
+
+/// Quote given csv value if needed.
+fn csv_value(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+pub(super) fn write_csv<W: Write>(
+    writer: &mut W,
+    spans: &HashMap<u64, Span>,
+) -> std::io::Result<()> {
+    let table = Table::new(spans);
+    write!(writer, "{}", FIXED_COLUMNS.join(","))?;
+    for name in &table.field_names {
+        write!(writer, ",{}", csv_value(&Table::column_name(name)))?;
+    }
+    writeln!(writer)?;
+    for span in &table.rows {
+        write!(
+            writer,
+            "{},{},{},{},{},{},{},{},{}",
+            span.id,
+            span.parent.map(|p| p.to_string()).unwrap_or_default(),
+            csv_value(span.name),
+            span.start,
+            span.end,
+            span.end - span.start,
+            span.execution_thread,
+            span.creation_thread,
+            table.depths[&span.id]
+        )?;
+        for name in &table.field_names {
+            write!(
+                writer,
+                ",{}",
+                csv_value(Table::field(span, name).unwrap_or(""))
+            )?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Records the execution of `op` and saves all spans as a csv file.
+pub fn csv<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(path: P, op: F) -> std::io::Result<R> {
+    let (r, trace) = Trace::record(op);
+    trace.save_csv(path)?;
+    Ok(r)
+}
+
+/// Shortcut for `Trace::extract().save_csv(path)`.
+pub fn dump_csv<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+    Trace::extract().save_csv(path)
+}
+
+#[cfg(feature = "arrow")]
+mod arrow {
+    use super::super::{Span, Trace};
+    use super::{Table, FIXED_COLUMNS};
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt32Array, UInt64Array};
+    use arrow_schema::{ArrowError, DataType, Field, Schema};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn to_io_error(error: ArrowError) -> std::io::Error {
+        std::io::Error::other(error)
+    }
+
+    pub(in super::super) fn write_arrow<W: std::io::Write>(
+        writer: W,
+        spans: &HashMap<u64, Span>,
+    ) -> std::io::Result<()> {
+        let table = Table::new(spans);
+        let u64_column = |f: &dyn Fn(&Span) -> u64| -> ArrayRef {
+            Arc::new(UInt64Array::from_iter_values(
+                table.rows.iter().map(|s| f(s)),
+            ))
+        };
+        let types = [
+            (DataType::UInt64, false),
+            (DataType::UInt64, true),
+            (DataType::Utf8, false),
+            (DataType::UInt64, false),
+            (DataType::UInt64, false),
+            (DataType::UInt64, false),
+            (DataType::UInt64, false),
+            (DataType::UInt64, false),
+            (DataType::UInt32, false),
+        ];
+        let mut fields = FIXED_COLUMNS
+            .iter()
+            .zip(types)
+            .map(|(name, (data_type, nullable))| Field::new(*name, data_type, nullable))
+            .collect::<Vec<_>>();
+        let mut columns: Vec<ArrayRef> = vec![
+            u64_column(&|s| s.id),
+            Arc::new(table.rows.iter().map(|s| s.parent).collect::<UInt64Array>()),
+            Arc::new(StringArray::from_iter_values(
+                table.rows.iter().map(|s| s.name),
+            )),
+            u64_column(&|s| s.start as u64),
+            u64_column(&|s| s.end as u64),
+            u64_column(&|s| (s.end - s.start) as u64),
+            u64_column(&|s| s.execution_thread as u64),
+            u64_column(&|s| s.creation_thread as u64),
+            Arc::new(UInt32Array::from_iter_values(
+                table.rows.iter().map(|s| table.depths[&s.id] as u32),
+            )),
+        ];
+        for name in &table.field_names {
+            fields.push(Field::new(Table::column_name(name), DataType::Utf8, true));
+            columns.push(Arc::new(
+                table
+                    .rows
+                    .iter()
+                    .map(|s| Table::field(s, name))
+                    .collect::<StringArray>(),
+            ));
+        }
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), columns).map_err(to_io_error)?;
+        let mut arrow_writer =
+            arrow_ipc::writer::FileWriter::try_new(writer, &schema).map_err(to_io_error)?;
+        arrow_writer.write(&batch).map_err(to_io_error)?;
+        arrow_writer.finish().map_err(to_io_error)
+    }
+
+    /// Records the execution of `op` and saves all spans as an arrow ipc file.
+    pub fn arrow<P: AsRef<std::path::Path>, R, F: FnOnce() -> R>(
+        path: P,
+        op: F,
+    ) -> std::io::Result<R> {
+        let (r, trace) = Trace::record(op);
+        trace.save_arrow(path)?;
+        Ok(r)
+    }
+
+    /// Shortcut for `Trace::extract().save_arrow(path)`.
+    pub fn dump_arrow<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<()> {
+        Trace::extract().save_arrow(path)
+    }
+}
+#[cfg(feature = "arrow")]
+pub(super) use self::arrow::write_arrow;
+#[cfg(feature = "arrow")]
+pub use self::arrow::{arrow, dump_arrow};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn spans() -> HashMap<u64, Span> {
+        let mut root = Span::new(1);
+        root.name = "root";
+        root.end = 10;
+        let mut child = Span::new(2);
+        child.name = "child, quoted";
+        child.parent = Some(1);
+        child.start = 2;
+        child.end = 5;
+        child.fields.push(("phase", "init"));
+        child.fields.push(("name", "field"));
+        vec![(1, root), (2, child)].into_iter().collect()
+    }
+    #[test]
+    fn csv_test() {
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &spans()).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,parent,name,start,end,duration,execution_thread,creation_thread,depth,field.name,phase\n\
+             1,,root,0,10,10,0,0,0,,\n\
+             2,1,\"child, quoted\",2,5,3,0,0,1,field,init\n"
+        );
+    }
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn arrow_test() {
+        let mut file = Vec::new();
+        arrow::write_arrow(&mut file, &spans()).unwrap();
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(file), None).unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[0].num_columns(), 11);
+        assert!(batches[0].schema().field_with_name("field.name").is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+//! Instrumented versions of `std::thread::spawn` and `std::thread::scope`.
+//!
+//! Spawning records a `spawn` fork span on the spawning thread (or a `scope`
+//! fork span covering the whole scope). Each child thread executes inside a
+//! `thread` span whose parent is this fork span, so that the graph links
+//! threads to where they were spawned.
+//! Child threads also use the spawning thread's subscriber.
+use super::events::name_thread;
+use std::thread::{JoinHandle, ScopedJoinHandle};
+use tracing::{dispatcher, span, Dispatch, Id, Level};
+
+/// Run `f` in the child thread, inside a `thread` span child of `parent`.
+fn run_child<T, F: FnOnce() -> T>(dispatch: Dispatch, parent: Option<Id>, f: F) -> T {
+    dispatcher::with_default(&dispatch, || {
+        let current = std::thread::current();
+        match current.name() {
+            Some(name) => name_thread(name),
+            None => name_thread(&format!("{:?}", current.id())),
+        }
+        let span = span!(parent: parent, Level::TRACE, "thread");
+        let _enter = span.enter();
+        f()
+    })
+}
+
+/// Like `std::thread::spawn` but links the new thread's spans to the current span.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with(std::thread::Builder::new(), f).expect("failed to spawn thread")
+}
+
+/// Like `std::thread::Builder::spawn` but links the new thread's spans to the current span.
+/// The builder's thread name (if any) names the thread in exports.
+pub fn spawn_with<F, T>(builder: std::thread::Builder, f: F) -> std::io::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let dispatch = dispatcher::get_default(|dispatch| dispatch.clone());
+    let span = span!(Level::TRACE, "spawn", fast_tracer.kind = "fork");
+    let parent = span.id();
+    let _enter = span.enter();
+    builder.spawn(move || run_child(dispatch, parent, f))
+}
+
+/// A scope to spawn scoped threads in (see `scope`).
+pub struct Scope<'scope, 'env: 'scope> {
+    scope: &'scope std::thread::Scope<'scope, 'env>,
+    parent: Option<Id>,
+    dispatch: Dispatch,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Like `std::thread::Scope::spawn` but links the new thread's spans to the scope's span.
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let dispatch = self.dispatch.clone();
+        let parent = self.parent.clone();
+        self.scope.spawn(move || run_child(dispatch, parent, f))
+    }
+}
+
+/// Like `std::thread::scope` but records a `scope` span, parent of all threads spawned in it.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>) -> T,
+{
+    let dispatch = dispatcher::get_default(|dispatch| dispatch.clone());
+    let span = span!(Level::TRACE, "scope", fast_tracer.kind = "fork");
+    let parent = span.id();
+    let _enter = span.enter();
+    std::thread::scope(|scope| {
+        f(&Scope {
+            scope,
+            parent,
+            dispatch,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::events::{extract_spans, RECORDING_TESTS};
+    use super::super::FastSubscriber;
+    use super::{scope, spawn_with};
+    use tracing::{dispatcher, Dispatch};
+
+    #[test]
+    fn spawn_test() {
+        let _recording = RECORDING_TESTS.lock().unwrap();
+        let dispatch = Dispatch::new(FastSubscriber::new());
+        dispatcher::with_default(&dispatch, || {
+            let builder = std::thread::Builder::new().name("child".to_owned());
+            spawn_with(builder, || ()).unwrap().join().unwrap();
+            scope(|scope| {
+                scope.spawn(|| ());
+            });
+        });
+        let spans = extract_spans();
+        let parent_name = |span: &super::super::Span| span.parent.map(|p| spans[&p].name);
+        let threads = spans
+            .values()
+            .filter(|s| s.name == "thread")
+            .collect::<Vec<_>>();
+        assert_eq!(threads.len(), 2);
+        assert!(threads.iter().any(
+            |s| parent_name(s) == Some("spawn") && s.fields.contains(&("thread.name", "child"))
+        ));
+        assert!(threads.iter().any(|s| parent_name(s) == Some("scope")));
+    }
+}
@@ -0,0 +1,102 @@
+//! A set of spans, recorded or imported, which can be saved in all supported formats.
+use super::clock::start_unix_nanos;
+use super::dot::write_dot;
+use super::events::extract_spans_with_offset;
+use super::firefox::write_firefox_profile;
+use super::otlp::write_otlp;
+use super::paje::write_paje;
+use super::svg::Gantt;
+use super::table::write_csv;
+use super::{reset_events, FastSubscriber, Graph, Span};
+use std::collections::HashMap;
+use tracing::{span, Level};
+
+/// All spans of a trace.
+/// A `Trace` is either extracted from what the `FastSubscriber` recorded
+/// or imported from another tool's trace file.
+#[derive(Debug)]
+pub struct Trace {
+    pub(super) spans: HashMap<u64, Span>,
+    /// Unix time (in nano seconds) of time 0 in the spans.
+    pub(super) origin: u128,
+    /// Where to save the gantt diagram when an assertion fails.
+    pub(super) failure_svg: Option<std::path::PathBuf>,
+}
+
+impl Trace {
+    /// Extract all spans recorded since the last extraction
+    /// (or since the start of the program).
+    pub fn extract() -> Self {
+        let (offset, spans) = extract_spans_with_offset();
+        Trace {
+            spans,
+            origin: start_unix_nanos() + offset,
+            failure_svg: None,
+        }
+    }
+    /// Records the execution of `op` inside a `main_task` span
+    /// (installing a `FastSubscriber` as global default if there is none yet)
+    /// and extracts its trace.
+    /// All the `op`-taking functions of the crate go through here.
+    /// While recording is paused (see `pause`) the trace is empty.
+    ///
+    /// Recording discards spans recorded before and extraction takes all spans
+    /// recorded since: recordings running at the same time (like tests of a same binary)
+    /// get mixed up and must be serialized.
+    ///
+    /// ```
+    /// let (sum, trace) = fast_tracer::Trace::record(|| (0..10).sum::<u32>());
+    /// assert_eq!(sum, 45);
+    /// trace.assert_span_count("main_task", 1);
+    /// ```
+    pub fn record<R, F: FnOnce() -> R>(op: F) -> (R, Self) {
+        let subscriber: FastSubscriber = FastSubscriber::new();
+        tracing::subscriber::set_global_default(subscriber).err();
+        reset_events();
+        let span = span!(Level::TRACE, "main_task");
+        let r = {
+            let _enter = span.enter();
+            op()
+        };
+        (r, Trace::extract())
+    }
+    /// Saves an svg displaying the tasks graph.
+    pub fn save_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        Graph::new(&self.spans).save_svg(path)
+    }
+    /// Saves an svg displaying the gantt diagram.
+    pub fn save_gantt_svg<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        Gantt::new(&self.spans).save_svg(path)
+    }
+    /// Saves the trace as a Paje trace file.
+    pub fn save_paje<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        write_paje(&mut create(path)?, &self.spans)
+    }
+    /// Saves the tasks graph as a graphviz dot file.
+    pub fn save_dot<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        write_dot(&mut create(path)?, &Graph::new(&self.spans))
+    }
+    /// Saves the trace as an OTLP json file.
+    pub fn save_otlp<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        write_otlp(&mut create(path)?, &self.spans, self.origin)
+    }
+    /// Saves the trace as a Firefox Profiler processed profile.
+    pub fn save_firefox_profile<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        write_firefox_profile(&mut create(path)?, &self.spans, self.origin)
+    }
+    /// Saves all spans as a csv file.
+    pub fn save_csv<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        write_csv(&mut create(path)?, &self.spans)
+    }
+    /// Saves all spans as an arrow ipc file.
+    #[cfg(feature = "arrow")]
+    pub fn save_arrow<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        super::table::write_arrow(std::fs::File::create(path)?, &self.spans)
+    }
+}
+
+fn create<P: AsRef<std::path::Path>>(
+    path: P,
+) -> std::io::Result<std::io::BufWriter<std::fs::File>> {
+    Ok(std::io::BufWriter::new(std::fs::File::create(path)?))
+}
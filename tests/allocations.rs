@@ -0,0 +1,89 @@
+//! Memory reclaimed by extractions, measured with the `TracingAllocator`
+//! as global allocator (which it can only be in its own test binary).
+use fast_tracer::{Trace, TracingAllocator};
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::sync::Mutex;
+use tracing::{span, Level};
+
+/// Bytes allocated and freed by the current thread.
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTERS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+fn count(allocated: usize, freed: usize) {
+    COUNTERS
+        .try_with(|c| {
+            let (a, f) = c.get();
+            c.set((a + allocated as u64, f + freed as u64))
+        })
+        .ok();
+}
+
+/// Bytes allocated and freed so far by the current thread.
+fn counters() -> (u64, u64) {
+    COUNTERS.with(|c| c.get())
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size(), 0);
+        TracingAllocator.alloc(layout)
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size(), 0);
+        TracingAllocator.alloc_zeroed(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count(0, layout.size());
+        TracingAllocator.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size, layout.size());
+        TracingAllocator.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Extractions take the events of all threads: recording tests cannot overlap.
+static RECORDING_TESTS: Mutex<()> = Mutex::new(());
+
+const SPANS: usize = 30_000;
+/// Each span logs at least an enter and an exit event of 16 bytes.
+const EVENTS_BYTES: u64 = SPANS as u64 * 2 * 16;
+
+fn record_spans() {
+    for _ in 0..SPANS {
+        span!(Level::TRACE, "reclaimed").in_scope(|| ())
+    }
+}
+
+#[test]
+fn finished_threads_reclaim_test() {
+    let _recording = RECORDING_TESTS.lock().unwrap();
+    // install the subscriber and forget anything recorded so far
+    drop(Trace::record(|| ()));
+    std::thread::spawn(record_spans).join().unwrap();
+    let (allocated, freed) = counters();
+    drop(Trace::extract());
+    let (allocated, freed) = (counters().0 - allocated, counters().1 - freed);
+    // the blocks of the finished thread are freed by the extracting thread
+    assert!(freed >= allocated + EVENTS_BYTES);
+}
+
+#[test]
+fn repeated_recordings_reclaim_test() {
+    let _recording = RECORDING_TESTS.lock().unwrap();
+    drop(Trace::record(record_spans));
+    let (allocated, freed) = counters();
+    for _ in 0..3 {
+        drop(Trace::record(record_spans));
+    }
+    let (allocated, freed) = (counters().0 - allocated, counters().1 - freed);
+    // resetting the logs frees all blocks but one
+    assert!(allocated < freed + EVENTS_BYTES);
+}
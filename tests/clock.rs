@@ -0,0 +1,40 @@
+//! A custom clock replaces the default one
+//! (which can only be checked in its own test binary, before any recording).
+use fast_tracer::{set_clock, Clock, InstantClock, Trace};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{span, Level};
+
+/// A clock advancing of one microsecond each time it is read.
+struct CountingClock(AtomicU64);
+
+impl Clock for CountingClock {
+    fn ticks(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst)
+    }
+    fn nanos_per_tick(&self) -> f64 {
+        1_000.0
+    }
+}
+
+#[test]
+fn set_clock_test() {
+    assert!(set_clock(CountingClock(AtomicU64::new(0))));
+    let ((), trace) = Trace::record(|| span!(Level::TRACE, "timed").in_scope(|| ()));
+    // the clock is in use: it cannot be replaced anymore
+    assert!(!set_clock(InstantClock::new()));
+    let path = std::env::temp_dir().join(format!("fast_tracer_clock_{}.csv", std::process::id()));
+    trace.save_csv(&path).unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let rows = csv.lines().skip(1).collect::<Vec<_>>();
+    assert_eq!(rows.len(), 2);
+    for row in rows {
+        let columns = row.split(',').collect::<Vec<_>>();
+        // start, end and duration are whole numbers of ticks
+        for time in &columns[3..6] {
+            let time: u128 = time.parse().unwrap();
+            assert_eq!(time % 1_000, 0);
+        }
+        assert_ne!(columns[5], "0");
+    }
+}